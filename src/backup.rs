@@ -0,0 +1,228 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{deploy::{CallRemote, Deploy, DeployError, DeployResult, ErrorKind, PlanEntry, shell_quote}, manifest::DeployManifest};
+
+/// Files are uploaded under this suffix and renamed into place afterwards
+pub const TEMP_SUFFIX: &str = ".condep-new";
+/// Previous version of a replaced file
+pub const BACKUP_SUFFIX: &str = ".condep-bak";
+/// Device file replaced by the first deploy to its path, put back by uninstall
+pub const ORIGINAL_SUFFIX: &str = ".condep-orig";
+/// Name of the backup record in the device directory
+pub const RECORD_FILE_NAME: &str = "backup.json";
+
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_os_string();
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+/// One replaced file as addressed in remote commands
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupEntry {
+    pub dst: PathBuf,
+    /// `None` if the file did not exist before deploy
+    pub backup: Option<PathBuf>
+}
+
+/// Files replaced by the last deploy to a device
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BackupRecord {
+    pub files: Vec<BackupEntry>
+}
+
+fn record_err(path: &Path, err: impl std::fmt::Display) -> DeployError {
+    DeployError::new(ErrorKind::History, Box::new(format!("{:?}: {}", path, err)))
+}
+
+impl BackupRecord {
+    /// `None` if nothing was deployed to the device since the last rollback
+    pub fn load(device_dir: &Path) -> DeployResult<Option<Self>> {
+        let path = device_dir.join(RECORD_FILE_NAME);
+        match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(bytes.as_slice())
+                .map(Some)
+                .map_err(|err| record_err(&path, err)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(record_err(&path, err))
+        }
+    }
+
+    pub fn save(&self, device_dir: &Path) -> DeployResult<()> {
+        let path = device_dir.join(RECORD_FILE_NAME);
+        std::fs::create_dir_all(device_dir)
+            .and_then(|()| std::fs::write(&path, serde_json::to_vec_pretty(self).unwrap()))
+            .map_err(|err| record_err(&path, err))
+    }
+
+    pub fn remove(device_dir: &Path) -> DeployResult<()> {
+        let path = device_dir.join(RECORD_FILE_NAME);
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(record_err(&path, err)),
+            _ => Ok(())
+        }
+    }
+
+    /// Moves backups back in place and removes files which did not exist before
+    pub fn restore<R: CallRemote + ?Sized>(&self, remote: &mut R) -> DeployResult<()> {
+        if self.files.is_empty() {
+            return Ok(());
+        }
+        let cmd = self.files
+            .iter()
+            .map(|f| match &f.backup {
+                Some(backup) => format!("mv -f {} {}", quote(backup), quote(&f.dst)),
+                None => format!("rm -f {}", quote(&f.dst)),
+            })
+            .collect::<Vec<_>>()
+            .join(" && ");
+        remote.call_remote(cmd.as_bytes())?.check(cmd.as_bytes()).map(|_| ())
+    }
+
+    /// Removes backup files from the device
+    pub fn discard<R: CallRemote + ?Sized>(&self, remote: &mut R) -> DeployResult<()> {
+        let backups: Vec<String> = self.files.iter().filter_map(|f| f.backup.as_deref()).map(quote).collect();
+        if backups.is_empty() {
+            return Ok(());
+        }
+        let cmd = format!("rm -f {}", backups.join(" "));
+        remote.call_remote(cmd.as_bytes())?.check(cmd.as_bytes()).map(|_| ())
+    }
+}
+
+fn quote(path: &Path) -> String {
+    shell_quote(&path.to_string_lossy())
+}
+
+/// Sets owner and group of `files` with one `chown` per distinct owner and group
+fn chown<R: CallRemote + ?Sized>(remote: &mut R, files: &[PlanEntry]) -> DeployResult<()> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in files.iter().filter(|e| e.link.is_none()) {
+        let spec = match (&entry.owner, &entry.group) {
+            (None, None) => continue,
+            (Some(owner), None) => owner.clone(),
+            (owner, Some(group)) => format!("{}:{}", owner.as_deref().unwrap_or(""), group),
+        };
+        groups.entry(spec).or_default().push(quote(&remote.remote_path(&entry.dst)));
+    }
+    if groups.is_empty() {
+        return Ok(());
+    }
+    let cmd = groups
+        .iter()
+        .map(|(spec, paths)| format!("chown {} {}", shell_quote(spec), paths.join(" ")))
+        .collect::<Vec<_>>()
+        .join(" && ");
+    remote.call_remote(cmd.as_bytes())?.check(cmd.as_bytes()).map(|_| ())
+}
+
+/// Uploads every file under a temporary name, backs up the files being replaced and renames the new ones into place.
+/// The record is saved to `device_dir` before anything is replaced so a failed deploy can be rolled back too.
+/// Files not yet in the manifest of `device_dir` are also kept as `ORIGINAL_SUFFIX` for uninstall
+pub fn deploy_atomic<D: Deploy + CallRemote + ?Sized>(depl: &mut D, files: &[PlanEntry], device_dir: &Path) -> DeployResult<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let temp: Vec<PlanEntry> = files
+        .iter()
+        .map(|e| PlanEntry { dst: with_suffix(&e.dst, TEMP_SUFFIX), ..e.clone() })
+        .collect();
+    depl.deploy(&temp)?;
+    chown(depl, &temp)?;
+
+    let dsts: Vec<PathBuf> = files.iter().map(|e| depl.remote_path(&e.dst)).collect();
+    let managed = DeployManifest::load(device_dir)?;
+
+    // hard link keeps the old file in place until it is replaced by rename
+    let backup_cmd = dsts
+        .iter()
+        .map(|dst| {
+            let backup = quote(&with_suffix(dst, BACKUP_SUFFIX));
+            let original = match managed.files.iter().any(|f| &f.dst == dst) {
+                true => String::new(),
+                false => format!(
+                    "if [ -e {dst} ] || [ -L {dst} ]; then ln -f {dst} {original} 2>/dev/null || cp -p {dst} {original}; fi && ",
+                    dst = quote(dst),
+                    original = quote(&with_suffix(dst, ORIGINAL_SUFFIX))
+                ),
+            };
+            format!(
+                "{original}if [ -e {dst} ] || [ -L {dst} ]; then {{ ln -f {dst} {backup} 2>/dev/null || cp -p {dst} {backup}; }} && echo {backup}; fi",
+                original = original,
+                dst = quote(dst),
+                backup = backup
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" && ");
+    let out = depl.call_remote(backup_cmd.as_bytes())?.check(backup_cmd.as_bytes())?;
+    let stdout = out.stdout_str();
+    let backed_up: Vec<&str> = stdout.lines().collect();
+
+    BackupRecord {
+        files: dsts
+            .iter()
+            .map(|dst| {
+                let backup = with_suffix(dst, BACKUP_SUFFIX);
+                BackupEntry {
+                    backup: if backed_up.contains(&backup.to_string_lossy().as_ref()) { Some(backup) } else { None },
+                    dst: dst.clone()
+                }
+            })
+            .collect()
+    }.save(device_dir)?;
+
+    let rename_cmd = dsts
+        .iter()
+        .map(|dst| format!("mv -f {} {}", quote(&with_suffix(dst, TEMP_SUFFIX)), quote(dst)))
+        .collect::<Vec<_>>()
+        .join(" && ");
+    depl.call_remote(rename_cmd.as_bytes())?.check(rename_cmd.as_bytes()).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{deploy::FileKind, local_deploy::{LocalDeploy, LocalDeployConfig}};
+
+    #[test]
+    fn rollback_restores_the_replaced_files() {
+        let dir = std::env::temp_dir().join(format!("condep-backup-{}", std::process::id()));
+        let (root, device_dir) = (dir.join("root"), dir.join("device"));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        std::fs::write(root.join("bin/tool"), "v1").unwrap();
+        std::fs::write(dir.join("tool"), "v2").unwrap();
+        std::fs::write(dir.join("new"), "n").unwrap();
+
+        let entry = |name: &str| PlanEntry {
+            kind: FileKind::Exec, src: dir.join(name), dst: Path::new("/bin").join(name), size: 2, mode: 0o755,
+            owner: None, group: None, link: None, overwrites: None
+        };
+        let mut depl = LocalDeploy::new(LocalDeployConfig { root: root.clone(), commands: Default::default(), hooks: Default::default() }).unwrap();
+        deploy_atomic(&mut depl, &[entry("tool"), entry("new")], &device_dir).unwrap();
+
+        let read = |name: &str| std::fs::read_to_string(root.join("bin").join(name)).ok();
+        assert_eq!(read("tool").as_deref(), Some("v2"));
+        assert_eq!(read("new").as_deref(), Some("n"));
+        assert_eq!(read("tool.condep-bak").as_deref(), Some("v1"));
+        assert_eq!(read("tool.condep-orig").as_deref(), Some("v1"));
+        assert_eq!(read("tool.condep-new"), None);
+
+        let record = BackupRecord::load(&device_dir).unwrap().unwrap();
+        let backups: Vec<_> = record.files.iter().map(|f| (f.dst.clone(), f.backup.clone())).collect();
+        assert_eq!(backups, [
+            (PathBuf::from("bin/tool"), Some(PathBuf::from("bin/tool.condep-bak"))),
+            (PathBuf::from("bin/new"), None)
+        ]);
+
+        record.restore(&mut depl).unwrap();
+        assert_eq!(read("tool").as_deref(), Some("v1"));
+        assert_eq!(read("new"), None);
+        assert_eq!(read("tool.condep-bak"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
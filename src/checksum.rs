@@ -0,0 +1,140 @@
+use std::{collections::BTreeMap, io::Read, path::{Path, PathBuf}};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::deploy::{CallRemote, DeployError, DeployResult, PlanEntry, shell_quote};
+
+/// Hash algorithms in order of preference. Devices with a stripped down busybox often have only `md5sum`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Md5
+}
+
+/// Exit status of a shell when the command is not found
+const COMMAND_NOT_FOUND: i32 = 127;
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 2] = [Algorithm::Sha256, Algorithm::Md5];
+
+    pub fn command(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256sum",
+            Algorithm::Md5 => "md5sum",
+        }
+    }
+
+    fn hash_reader<D: Digest, R: Read>(mut reader: R) -> std::io::Result<String> {
+        let mut digest = D::new();
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            digest.update(&buf[..n]);
+        }
+        Ok(digest.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    pub fn hash_file(&self, path: &Path) -> std::io::Result<String> {
+        let file = std::fs::File::open(path)?;
+        match self {
+            Algorithm::Sha256 => Self::hash_reader::<Sha256, _>(file),
+            Algorithm::Md5 => Self::hash_reader::<Md5, _>(file),
+        }
+    }
+}
+
+/// Checksums of `paths` on the device. Missing files are absent from the map.
+/// `None` if the device has no `alg.command()`
+pub fn remote_checksums_with<R: CallRemote + ?Sized>(remote: &mut R, paths: &[PathBuf], alg: Algorithm) -> DeployResult<Option<BTreeMap<PathBuf, String>>> {
+    if paths.is_empty() {
+        return Ok(Some(BTreeMap::new()));
+    }
+
+    let args: Vec<String> = paths.iter().map(|p| shell_quote(&p.to_string_lossy())).collect();
+    let cmd = format!("{} {} 2>/dev/null", alg.command(), args.join(" "));
+    let out = remote.call_remote(cmd.as_bytes())?;
+    if out.status == COMMAND_NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(out
+        .stdout_str()
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(hash, path)| (PathBuf::from(path), hash.to_string()))
+        .collect()
+    ))
+}
+
+/// Checksums of `paths` on the device, computed with the first available algorithm.
+/// `None` if the device has no checksum tool
+pub fn remote_checksums<R: CallRemote + ?Sized>(remote: &mut R, paths: &[PathBuf]) -> DeployResult<Option<(Algorithm, BTreeMap<PathBuf, String>)>> {
+    for alg in Algorithm::ALL {
+        if let Some(sums) = remote_checksums_with(remote, paths, alg)? {
+            return Ok(Some((alg, sums)));
+        }
+    }
+    Ok(None)
+}
+
+/// Splits `entries` into files which differ from the device copy and files which are already there.
+/// Everything is uploaded if the device has no checksum tool
+pub fn split_unchanged<R: CallRemote + ?Sized>(remote: &mut R, entries: Vec<PlanEntry>) -> DeployResult<(Vec<PlanEntry>, Vec<PlanEntry>)> {
+    // symlinks are cheap to recreate and can not be compared by content
+    let (mut changed, entries): (Vec<PlanEntry>, Vec<PlanEntry>) = entries.into_iter().partition(|e| e.link.is_some());
+    let paths: Vec<PathBuf> = entries.iter().map(|e| remote.remote_path(&e.dst)).collect();
+    let (alg, sums) = match remote_checksums(remote, &paths)? {
+        Some(v) => v,
+        None => { changed.extend(entries); return Ok((changed, Vec::new())) }
+    };
+
+    let mut unchanged = Vec::new();
+    for (entry, path) in entries.into_iter().zip(paths) {
+        let local = alg
+            .hash_file(&entry.src)
+            .map_err(|err| DeployError::new_copy_err(Box::new(format!("{:?}: {}", entry.src, err))))?;
+        if sums.get(&path) == Some(&local) {
+            unchanged.push(entry);
+        } else {
+            changed.push(entry);
+        }
+    }
+    Ok((changed, unchanged))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{deploy::FileKind, local_deploy::{LocalDeploy, LocalDeployConfig}};
+
+    #[test]
+    fn identical_device_files_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("condep-checksum-{}", std::process::id()));
+        let root = dir.join("root");
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        for (name, content) in [("same", "1"), ("changed", "2"), ("missing", "3")] {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        std::fs::write(root.join("bin/same"), "1").unwrap();
+        std::fs::write(root.join("bin/changed"), "old").unwrap();
+        std::fs::write(root.join("bin/link"), "1").unwrap();
+
+        let entry = |name: &str, link: Option<&str>| PlanEntry {
+            kind: FileKind::Exec, src: dir.join(name), dst: Path::new("/bin").join(name), size: 1, mode: 0o755,
+            owner: None, group: None, link: link.map(PathBuf::from), overwrites: None
+        };
+        let mut depl = LocalDeploy::new(LocalDeployConfig { root, commands: Default::default(), hooks: Default::default() }).unwrap();
+        let (changed, unchanged) = split_unchanged(&mut depl, vec![
+            entry("same", None), entry("changed", None), entry("missing", None), entry("link", Some("same"))
+        ]).unwrap();
+
+        let names = |entries: &[PlanEntry]| entries.iter().map(|e| e.dst.display().to_string()).collect::<Vec<_>>();
+        assert_eq!(names(&changed), ["/bin/link", "/bin/changed", "/bin/missing"]);
+        assert_eq!(names(&unchanged), ["/bin/same"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -16,15 +16,15 @@ pub mod print {
     use termion::{color::{self, Reset, Fg, LightGreen, LightYellow, LightRed}, style::{self, Bold}};
 
     pub fn info(header: &str, str: String) {
-        println!("{}{}{}{}{} {}", Bold, Fg(LightGreen), header, style::Reset{}, Reset{}.fg_str(), str)
+        eprintln!("{}{}{}{}{} {}", Bold, Fg(LightGreen), header, style::Reset{}, Reset{}.fg_str(), str)
     }
     
     pub fn warning(header: &str, str: String) {
-        println!("{}{}{}{}{} {}", Bold, Fg(LightYellow), header, style::Reset{}, color::Reset{}.fg_str(), str)    
+        eprintln!("{}{}{}{}{} {}", Bold, Fg(LightYellow), header, style::Reset{}, color::Reset{}.fg_str(), str)    
     }
     
-    pub fn fatal(header: &str, str: String) {
-        println!("{}{}{}{}{} {}", Bold, Fg(LightRed), header, style::Reset{}, Reset{}.fg_str(), str);
+    pub fn fatal(header: &str, str: String) -> ! {
+        eprintln!("{}{}{}{}{} {}", Bold, Fg(LightRed), header, style::Reset{}, Reset{}.fg_str(), str);
         exit(-1)
     }    
 
@@ -36,6 +36,23 @@ pub mod print {
     pub const CAN_NOT_CREATE_LINK: &str = " Link failed";
     pub const ENV_DUMPING_FAILED:  &str = " Dump failed";                                             
     pub const BAD_STATUS:          &str = "  Bad status";                                             
+    pub const BAD_MANIFEST:        &str = "Bad manifest";
+    pub const BAD_CONFIG:          &str = "  Bad config";
+    pub const NOTHING_TO_DEPLOY:   &str = "No artifacts";
+    pub const HOOK_FAILED:         &str = " Hook failed";
+    pub const DEPLOY_FAILED:       &str = "Deploy error";
+    pub const HOST_KEY_ADDED:      &str = "Host key new";
+    pub const DEPLOYED:            &str = "    Deployed";
+    pub const ROLLED_BACK:         &str = " Rolled back";
+    pub const UNINSTALLED:         &str = " Uninstalled";
+    pub const DEPENDENCY:          &str = "  Dependency";
+    pub const MISSING_LIB:         &str = " Missing lib";
+    pub const INCOMPATIBLE:        &str = "Incompatible";
+    pub const CHECKED:             &str = "     Checked";
+    pub const CHECK_FAILED:        &str = "Check failed";
+    pub const STRIPPED:            &str = "    Stripped";
+    pub const GDBSERVER:           &str = "   Gdbserver";
+    pub const LAUNCH_ADDED:        &str = "Launch added";
     
     
 }
@@ -287,7 +304,23 @@ pub struct BuildConfiguration {
     sources: Vec<EnvStr>,
     soft_links: Vec<LinkSource>,
     linker: Option<EnvStr>,
-    link_paths: Vec<EnvStr>
+    link_paths: Vec<EnvStr>,
+    /// Root of the target filesystem in the toolchain (e.g. `$TOOLCHAIN_PATH/$TOOLCHAIN_PREFIX/sysroot`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sysroot: Option<EnvStr>,
+    /// Environment applied by `cargo condep run` on top of `[env]` but not to builds (e.g. `QT_PLUGIN_PATH`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    run_env: Vec<EnvPair>
+}
+
+/// Directories where shared libraries the artifacts link to are searched: `link_paths`, then the sysroot lib dirs
+fn lib_search_paths(link_paths: &[EnvStr], sysroot: Option<&EnvStr>) -> Vec<PathBuf> {
+    let sysroot = sysroot.map(|s| PathBuf::from(s.to_string()));
+    link_paths
+        .iter()
+        .map(|p| PathBuf::from(p.to_string()))
+        .chain(sysroot.iter().flat_map(|s| ["lib", "usr/lib", "usr/local/lib"].map(|d| s.join(d))))
+        .collect()
 }
 
 #[derive(Debug)]
@@ -355,6 +388,39 @@ pub fn merge_environment(top: BTreeMap<String, String>) {
     }
 }
 
+/// Runs every script of `sources` and merges the environment it leaves into the process environment.
+/// Stops at the first script which does not exist or fails
+fn apply_sources(sources: &[EnvStr], log_level: &LogLevel) -> Result<(), String> {
+    for src in sources {
+        let cmd = src.to_path().map_err(|err| format!("{}: {}", src.to_str(), err))?;
+        match dump_environment(&cmd.to_string_lossy().into_owned()) {
+            Ok(envmap) => {
+                if log_level.print_pretty() {
+                    print::info(print::ENV_DUMPED, String::new());
+                }
+
+                if log_level.print_verbose() {
+                    for (k, v) in &envmap {
+                        eprintln!(
+                            "{}{}{} -> {}{}{}", 
+                            termion::color::Bg(termion::color::Magenta), 
+                            k, 
+                            termion::color::Reset{}.bg_str(),
+                            termion::color::Fg(termion::color::Green),
+                            v, 
+                            termion::color::Reset{}.fg_str()
+                        )
+                    }
+                }
+
+                merge_environment(envmap)
+            },
+            Err(err) => return Err(format!("{:?}: {:?}", cmd, err)),
+        }
+    }
+    Ok(())
+}
+
 pub enum LogLevel {
     Off,
     Pretty,
@@ -372,6 +438,45 @@ impl From<&str> for LogLevel {
     }
 }
 
+/// `cargo condep run` mode written as the cargo target runner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunnerMode {
+    /// Run on the host with the configured environment
+    Host,
+    /// Upload to the device and run there
+    Remote,
+    /// Run under `qemu-<arch>` user mode emulation with the target sysroot
+    Qemu
+}
+
+impl std::str::FromStr for RunnerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(RunnerMode::Host),
+            "remote" => Ok(RunnerMode::Remote),
+            "qemu" => Ok(RunnerMode::Qemu),
+            _ => Err(format!("unknown runner {:?}, expected host, remote or qemu", s))
+        }
+    }
+}
+
+impl RunnerMode {
+    /// Value of `target.<triple>.runner`. Written as an array since cargo splits a string on whitespace
+    /// and the sysroot path may contain spaces
+    pub fn command(&self, sysroot: Option<&Path>) -> Vec<String> {
+        let mut cmd: Vec<String> = ["cargo", "condep", "run"].map(String::from).into();
+        match (self, sysroot) {
+            (RunnerMode::Host, _) => {},
+            (RunnerMode::Remote, _) => cmd.push("--remote".into()),
+            (RunnerMode::Qemu, Some(sysroot)) => cmd.extend(["--qemu".into(), "--sysroot".into(), sysroot.to_string_lossy().into_owned()]),
+            (RunnerMode::Qemu, None) => cmd.push("--qemu".into()),
+        }
+        cmd
+    }
+}
+
 impl LogLevel {
     pub fn print_pretty(&self) -> bool {
         match self {
@@ -391,36 +496,40 @@ impl LogLevel {
 
 impl BuildConfiguration {
     pub fn new(env: Vec<EnvPair>, sources: Vec<EnvStr>, soft_links: Vec<LinkSource>, linker: Option<EnvStr>, link_paths: Vec<EnvStr>) -> Self {
-        BuildConfiguration { env: env, sources: sources, soft_links: soft_links, linker: linker, link_paths: link_paths }
+        BuildConfiguration { env: env, sources: sources, soft_links: soft_links, linker: linker, link_paths: link_paths, sysroot: None, run_env: Vec::new() }
     }
 
-    pub fn to_env<F: Fn(&String) -> bool>(self, predicate: &F, log_level: LogLevel) -> Vec<(String, String)> {
-        for src in self.sources.into_iter() {
-            let cmd = src.to_path().unwrap();
-            match dump_environment(&String::from(cmd.as_os_str().to_str().unwrap())) {
-                Ok(envmap) => {
-                    if log_level.print_pretty() {
-                        print::info(print::ENV_DUMPED, String::new());
-                    }
+    pub fn with_sysroot(self, sysroot: EnvStr) -> Self {
+        BuildConfiguration { sysroot: Some(sysroot), ..self }
+    }
 
-                    if log_level.print_verbose() {
-                        for (k, v) in &envmap {
-                            println!(
-                                "{}{}{} -> {}{}{}", 
-                                termion::color::Bg(termion::color::Magenta), 
-                                k, 
-                                termion::color::Reset{}.bg_str(),
-                                termion::color::Fg(termion::color::Green),
-                                v, 
-                                termion::color::Reset{}.fg_str()
-                            )
-                        }
-                    }
+    pub fn with_run_env(self, run_env: Vec<EnvPair>) -> Self {
+        BuildConfiguration { run_env, ..self }
+    }
 
-                    merge_environment(envmap)
-                },
-                Err(err) => print::fatal(print::ENV_DUMPING_FAILED, format!("{:?}", err)),
-            }
+    /// Runs `sources` like `to_env` does, then picks an alternative for every `run_env` variable and sets it
+    /// in the process environment (`get_env_pair` does it through `VarAction::convert`). Returns every variable
+    /// with the value set, `None` if no alternative matched `predicate`. A failed source is reported as a warning
+    pub fn apply_run_env<F: Fn(&String) -> bool>(&self, predicate: &F) -> Vec<(String, Option<String>)> {
+        if self.run_env.is_empty() {
+            return Vec::new();
+        }
+        if let Err(err) = apply_sources(&self.sources, &LogLevel::Off) {
+            print::warning(print::ENV_DUMPING_FAILED, err);
+        }
+        self.run_env
+            .iter()
+            .map(|pair| (pair.key.clone(), pair.value.get_env_pair(pair.key.clone(), predicate).map(|(_, v)| v)))
+            .collect()
+    }
+
+    pub fn sysroot(&self) -> Option<PathBuf> {
+        self.sysroot.as_ref().map(|s| PathBuf::from(s.to_string()))
+    }
+
+    pub fn to_env<F: Fn(&String) -> bool>(self, predicate: &F, log_level: LogLevel) -> Vec<(String, String)> {
+        if let Err(err) = apply_sources(&self.sources, &log_level) {
+            print::fatal(print::ENV_DUMPING_FAILED, err);
         }
 
         self
@@ -444,9 +553,9 @@ impl BuildConfiguration {
             .collect()
     }
 
-    pub fn make_links(&self) {
+    pub fn make_links(&self, dir: &Path) {
         for l in self.soft_links.iter() {
-            match l.clone().link_to(env::current_dir().unwrap().as_path()) {
+            match l.clone().link_to(dir) {
                 Ok((s, l)) => print::info(print::LINK_CREATED, format!("{:?} -> {}", l ,s)),
                 Err(err) => match err {
                     LinkError::IOError(err) => print::warning(print::CAN_NOT_CREATE_LINK, format!("{:?}: io error: {}", &l, err)),
@@ -468,6 +577,13 @@ impl BuildMultitargetConfig {
         BuildMultitargetConfig { targets: targets, default: default }
     }
 
+    pub fn get(&self, target_triple: &Option<String>) -> Option<&BuildConfiguration> {
+        match target_triple {
+            Some(tt) => self.targets.get(tt),
+            None => Some(&self.default),
+        }
+    }
+
     pub fn get_or_default(self, target_triple: &Option<String>) -> Option<BuildConfiguration> {
         match target_triple {
             Some(tt) => { 
@@ -481,92 +597,325 @@ impl BuildMultitargetConfig {
         }
     }
 
-    pub fn to_config_toml(self, target_triple: &Option<String>, log_level: LogLevel, alias: BTreeMap<String, String>) -> Option<toml::Config> {
+    /// Creates soft links in `links_dir` (usually the package root) and sets up the environment of the target
+    pub fn resolve(self, target_triple: &Option<String>, log_level: LogLevel, links_dir: &Path) -> Option<ResolvedBuild> {
         self.get_or_default(target_triple).map(|cunfiguration| {
-            cunfiguration.make_links();
+            cunfiguration.make_links(links_dir);
 
             let linker = cunfiguration.linker.clone();
             let link_paths = cunfiguration.link_paths.clone();
+            let sysroot = cunfiguration.sysroot.clone();
     
             let env_pairs = cunfiguration.to_env(&|s: &String| Path::new(s).exists(), log_level);
+            // expanded after `to_env` so variables set by `sources` are visible
+            let lib_search_paths = lib_search_paths(&link_paths, sysroot.as_ref());
+            let sysroot = sysroot.map(|s| PathBuf::from(s.to_string()));
                
             let links_array: Vec<String> = link_paths
                 .into_iter()
                 .map(|link| ["-L".into(), link.to_string()]).flatten()
                 .collect();
 
-            match target_triple {
-                Some(tgt) => toml::Config {
-                    alias: alias,
-                    build: toml::Build::with_target(tgt.clone(), links_array),             
-                    target: {
-                        let mut table = ::toml::map::Map::new();
-                        if let Some(linker) = linker {
-                            table.insert(toml::Config::LINKER.into(), linker.to_string().into());
-                        }
-                        BTreeMap::from([(tgt.clone(), table)])
-                    },
-                    env: BTreeMap::from_iter(env_pairs),
-                },
-                None => toml::Config {
-                    alias: alias,
-                    build: toml::Build::empty_target(links_array),
-                    target: {
-                        let current_target = rustc_vv()
-                            .unwrap()
-                            .into_iter()
-                            .find(|(k, _)| k == "host")
-                            .unwrap()
-                            .1;
-
-                        let mut table = ::toml::map::Map::new();
-                        table.insert(toml::Config::RUNNER.into(), "cargo condep run".into());
-                        BTreeMap::from([(current_target, table)])
-                    },
-                    env: BTreeMap::from_iter(env_pairs),
-                },
+            ResolvedBuild {
+                target: target_triple.clone(),
+                env: env_pairs,
+                linker: linker.map(|l| l.to_string()),
+                rustflags: links_array,
+                lib_search_paths,
+                sysroot
             }
         })
     }
 
+    pub fn to_config_toml(self, target_triple: &Option<String>, log_level: LogLevel, alias: BTreeMap<String, String>, runner: Option<RunnerMode>, links_dir: &Path) -> Option<toml::Config> {
+        self.resolve(target_triple, log_level, links_dir).map(|resolved| resolved.to_config_toml(alias, runner))
+    }
+
+}
+
+/// Build configuration of one target with env expanded
+#[derive(Debug)]
+pub struct ResolvedBuild {
+    pub target: Option<String>,
+    pub env: Vec<(String, String)>,
+    pub linker: Option<String>,
+    pub rustflags: Vec<String>,
+    /// `link_paths`, then the sysroot lib dirs, expanded after `sources`
+    pub lib_search_paths: Vec<PathBuf>,
+    pub sysroot: Option<PathBuf>
+}
+
+/// Program `name` of the cross toolchain: `var` of `env` if set, otherwise `name` with the prefix of the linker
+/// (`arm-linux-gnueabi-gcc` -> `arm-linux-gnueabi-<name>`). Host `name` if neither is known
+pub fn toolchain_tool(env: &BTreeMap<String, String>, linker: Option<&str>, var: &str, name: &str) -> PathBuf {
+    if let Some(path) = env.get(var) {
+        return PathBuf::from(path);
+    }
+    let linker = linker.map(Path::new);
+    let prefix = linker
+        .and_then(Path::file_name)
+        .and_then(|n| n.to_str())
+        .and_then(|n| n.rsplit_once('-'))
+        .map(|(prefix, _)| prefix);
+    match (linker, prefix) {
+        (Some(linker), Some(prefix)) => linker.with_file_name(format!("{}-{}", prefix, name)),
+        _ => PathBuf::from(name)
+    }
+}
+
+pub fn host_triple() -> String {
+    rustc_vv()
+        .unwrap()
+        .into_iter()
+        .find(|(k, _)| k == "host")
+        .unwrap()
+        .1
+}
+
+impl ResolvedBuild {
+    /// `runner` defaults to `RunnerMode::Remote` for cross targets and `RunnerMode::Host` otherwise
+    pub fn to_config_toml(self, alias: BTreeMap<String, String>, runner: Option<RunnerMode>) -> toml::Config {
+        let runner = runner
+            .unwrap_or(if self.target.is_some() { RunnerMode::Remote } else { RunnerMode::Host })
+            .command(self.sysroot.as_deref());
+        match self.target {
+            Some(tgt) => toml::Config {
+                alias: alias,
+                build: toml::Build::with_target(tgt.clone(), self.rustflags),             
+                target: {
+                    let mut table = ::toml::map::Map::new();
+                    if let Some(linker) = self.linker {
+                        table.insert(toml::Config::LINKER.into(), linker.into());
+                    }
+                    table.insert(toml::Config::RUNNER.into(), runner.into());
+                    BTreeMap::from([(tgt, table)])
+                },
+                env: self.env.into_iter().map(|(k, v)| (k, v.into())).collect(),
+            },
+            None => toml::Config {
+                alias: alias,
+                build: toml::Build::empty_target(self.rustflags),
+                target: {
+                    let mut table = ::toml::map::Map::new();
+                    table.insert(toml::Config::RUNNER.into(), runner.into());
+                    BTreeMap::from([(host_triple(), table)])
+                },
+                env: self.env.into_iter().map(|(k, v)| (k, v.into())).collect(),
+            },
+        }
+    }
+
+    /// Environment for a cargo child process equivalent to the generated `.cargo/config.toml`
+    pub fn cargo_env(&self) -> Vec<(String, String)> {
+        let mut env = self.env.clone();
+        if let (Some(tgt), Some(linker)) = (&self.target, &self.linker) {
+            env.push((format!("CARGO_TARGET_{}_LINKER", tgt.to_uppercase().replace(['-', '.'], "_")), linker.clone()));
+        }
+        let encoded = self.encoded_rustflags(env::var("CARGO_ENCODED_RUSTFLAGS").ok(), env::var("RUSTFLAGS").ok());
+        if let Some(encoded) = encoded {
+            env.push(("CARGO_ENCODED_RUSTFLAGS".into(), encoded));
+        }
+        env
+    }
+
+    /// `rustflags` appended to flags the user already passes with `CARGO_ENCODED_RUSTFLAGS` or `RUSTFLAGS`
+    /// (in cargo's order of precedence). `None` if there is nothing to add, so cargo keeps reading its own config
+    pub fn encoded_rustflags(&self, encoded: Option<String>, plain: Option<String>) -> Option<String> {
+        if self.rustflags.is_empty() {
+            return None;
+        }
+        let existing: Vec<String> = match (encoded, plain) {
+            (Some(encoded), _) => encoded.split('\x1f').filter(|f| !f.is_empty()).map(str::to_string).collect(),
+            (None, Some(plain)) => plain.split_whitespace().map(str::to_string).collect(),
+            (None, None) => Vec::new()
+        };
+        Some(existing.iter().chain(self.rustflags.iter()).cloned().collect::<Vec<_>>().join("\x1f"))
+    }
 }
 
 
 
 
 pub mod toml {
-    use std::collections::BTreeMap;
+    use std::{collections::BTreeMap, path::Path};
     use serde::{Deserialize, Serialize};
+    use toml_edit::{Document, Item, Table, TableLike, Value};
 
-    #[derive(Serialize, Deserialize, Debug)]
+    #[derive(Serialize, Deserialize, Debug, Default)]
     pub struct Build {
-        pub jobs: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub jobs: Option<usize>,
+        #[serde(default)]
         pub target: Option<String>,
+        #[serde(default)]
         pub rustflags: Vec<String>
     }
 
     impl Build {
         pub fn with_target(target: String, rustflags: Vec<String>) -> Self {
-            Build { jobs: num_cpus::get(), target: Some(target), rustflags: rustflags }
+            Build { jobs: None, target: Some(target), rustflags: rustflags }
         }
         pub fn empty_target(rustflags: Vec<String>) -> Self {
-            Build { jobs: num_cpus::get(), target: None, rustflags: rustflags }
+            Build { jobs: None, target: None, rustflags: rustflags }
         }
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
+    /// Only fields owned by condep are modeled, everything else in `.cargo/config.toml` is ignored
+    #[derive(Serialize, Deserialize, Debug, Default)]
     pub struct Config {
+        #[serde(default)]
         pub alias: BTreeMap<String, String>,
+        #[serde(default)]
         pub build: Build,
-        pub env: BTreeMap<String, String>,
+        #[serde(default)]
+        pub env: BTreeMap<String, EnvValue>,
+        #[serde(default)]
         pub target: BTreeMap<String, toml::value::Table>
     }
 
+    /// `[env]` entry: a plain string or `{ value = "..", force = true, relative = true }`
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    #[serde(untagged)]
+    pub enum EnvValue {
+        Plain(String),
+        Detailed {
+            value: String,
+            /// Overrides the variable if it is already set
+            #[serde(default)]
+            force: bool,
+            /// `value` is relative to the directory containing `.cargo`
+            #[serde(default)]
+            relative: bool
+        }
+    }
+
+    impl From<String> for EnvValue {
+        fn from(s: String) -> Self { EnvValue::Plain(s) }
+    }
+
+    impl EnvValue {
+        pub fn value(&self) -> &str {
+            match self {
+                EnvValue::Plain(value) => value,
+                EnvValue::Detailed { value, .. } => value,
+            }
+        }
+
+        fn to_toml(&self) -> Value {
+            match self {
+                EnvValue::Plain(value) => value.as_str().into(),
+                EnvValue::Detailed { value, force, relative } => {
+                    let mut table = toml_edit::InlineTable::new();
+                    table.insert("value", value.as_str().into());
+                    if *force {
+                        table.insert("force", true.into());
+                    }
+                    if *relative {
+                        table.insert("relative", true.into());
+                    }
+                    Value::InlineTable(table)
+                },
+            }
+        }
+    }
+
+    /// Suffix comment of every value written by condep.
+    /// Marked values are updated or removed on reconfigure, unmarked ones are left alone unless condep owns the key.
+    pub const MANAGED_MARK: &str = "# condep";
+
+    fn is_managed(item: &Item) -> bool {
+        item
+            .as_value()
+            .and_then(|v| v.decor().suffix())
+            .is_some_and(|s| s.contains(MANAGED_MARK))
+    }
+
+    /// Removes marked values which are not in `entries`. Returns true if `table` had content and became empty
+    fn remove_stale(table: &mut dyn TableLike, path: &mut Vec<String>, entries: &[(Vec<String>, Value)]) -> bool {
+        if table.is_empty() {
+            return false;
+        }
+        let keys: Vec<String> = table.iter().map(|(k, _)| k.to_string()).collect();
+        for key in keys {
+            path.push(key.clone());
+            let remove = match table.get_mut(&key) {
+                Some(item) if is_managed(item) => !entries.iter().any(|(p, _)| p == path),
+                Some(item) => item.as_table_like_mut().is_some_and(|t| remove_stale(t, path, entries)),
+                None => false
+            };
+            if remove {
+                table.remove(&key);
+            }
+            path.pop();
+        }
+        table.is_empty()
+    }
+
+    fn set_managed(table: &mut dyn TableLike, path: &[String], value: Value) {
+        match path {
+            [key] => {
+                let value = value.decorated(" ", &format!(" {}", MANAGED_MARK));
+                match table.get_mut(key) {
+                    Some(item) => *item = Item::Value(value),
+                    None => { table.insert(key, Item::Value(value)); },
+                }
+            },
+            [key, rest @ ..] => {
+                let item = table.entry(key).or_insert_with(|| {
+                    let mut t = Table::new();
+                    t.set_implicit(true);
+                    Item::Table(t)
+                });
+                if !item.is_table_like() {
+                    *item = Item::Table(Table::new());
+                }
+                set_managed(item.as_table_like_mut().unwrap(), rest, value)
+            },
+            [] => {}
+        }
+    }
+
     impl Config {
         pub const RUNNER: &'static str = "runner";
         pub const LINKER: &'static str = "linker";
         pub const RUSTC_LINK_SEARCH: &'static str = "rustc-link-search";
 
+        /// Reads keys owned by condep from `.cargo/config.toml` content. Values of other shapes cargo accepts
+        /// (`build.rustflags = "-C .."`, `alias.b = ["build", "--release"]`) are converted, anything else is skipped
+        pub fn parse(s: &str) -> Result<Config, toml::de::Error> {
+            let doc: toml::Value = toml::from_str(s)?;
+            let table = |key: &str| doc.get(key).and_then(toml::Value::as_table).cloned().unwrap_or_default();
+            let words = |v: &toml::Value| match v {
+                toml::Value::String(s) => Some(s.split_whitespace().map(str::to_string).collect::<Vec<_>>()),
+                toml::Value::Array(a) => a.iter().map(|v| v.as_str().map(str::to_string)).collect(),
+                _ => None
+            };
+
+            let build = table("build");
+            Ok(Config {
+                alias: table("alias")
+                    .iter()
+                    .filter_map(|(k, v)| words(v).map(|w| (k.clone(), w.join(" "))))
+                    .collect(),
+                build: Build {
+                    jobs: build.get("jobs").and_then(toml::Value::as_integer).and_then(|j| usize::try_from(j).ok()),
+                    target: build.get("target").and_then(toml::Value::as_str).map(str::to_string),
+                    rustflags: build.get("rustflags").and_then(words).unwrap_or_default()
+                },
+                env: table("env")
+                    .into_iter()
+                    .filter_map(|(k, v)| v.try_into::<EnvValue>().ok().map(|v| (k, v)))
+                    .collect(),
+                target: table("target")
+                    .into_iter()
+                    .filter_map(|(k, v)| match v {
+                        toml::Value::Table(t) => Some((k, t)),
+                        _ => None
+                    })
+                    .collect()
+            })
+        }
+
         pub fn target_mono(target: String, key: String, value: toml::Value) -> BTreeMap<String, toml::value::Table> {
             let mut table = toml::map::Map::new();
             table.insert(key, value);
@@ -584,6 +933,24 @@ pub mod toml {
                         toml::map::Entry::Occupied(o) => { let mut oo = o; Some(oo.insert(value)) },
                     }
         }
+        /// Values of `[env]` as they are written
+        pub fn env_values(&self) -> BTreeMap<String, String> {
+            self.env.iter().map(|(k, v)| (k.clone(), v.value().to_string())).collect()
+        }
+
+        /// `[env]` the way cargo applies it to processes it runs: variables already set in the process environment
+        /// are kept unless `force` is set, `relative` values are joined to `config_root` (the directory containing `.cargo`)
+        pub fn process_env(&self, config_root: &Path) -> Vec<(String, String)> {
+            self.env
+                .iter()
+                .filter(|(k, v)| matches!(v, EnvValue::Detailed { force: true, .. }) || std::env::var_os(k).is_none())
+                .map(|(k, v)| match v {
+                    EnvValue::Detailed { value, relative: true, .. } => (k.clone(), config_root.join(value).to_string_lossy().into_owned()),
+                    _ => (k.clone(), v.value().to_string()),
+                })
+                .collect()
+        }
+
         pub fn target_val(&self, target: &str, key: &str) -> Option<&str> {
             self
                 .target
@@ -591,19 +958,272 @@ pub mod toml {
                 .and_then(|table| table.get(key))
                 .and_then(|v| v.as_str())
         }
+
+        /// Keys owned by condep with their values
+        fn managed_entries(&self) -> Vec<(Vec<String>, Value)> {
+            let mut entries: Vec<(Vec<String>, Value)> = Vec::new();
+            if let Some(target) = &self.build.target {
+                entries.push((vec!["build".into(), "target".into()], target.as_str().into()));
+            }
+            // hand-written rustflags stay untouched when there is nothing to add
+            if !self.build.rustflags.is_empty() {
+                entries.push((vec!["build".into(), "rustflags".into()], Value::Array(self.build.rustflags.iter().collect())));
+            }
+            for (triple, table) in &self.target {
+                for key in [Self::LINKER, Self::RUNNER] {
+                    let value = match table.get(key) {
+                        Some(toml::Value::String(s)) => s.as_str().into(),
+                        // `runner` is written as an array of arguments
+                        Some(toml::Value::Array(a)) => Value::Array(a.iter().filter_map(toml::Value::as_str).collect()),
+                        _ => continue
+                    };
+                    entries.push((vec!["target".into(), triple.clone(), key.into()], value));
+                }
+            }
+            for (k, v) in &self.env {
+                entries.push((vec!["env".into(), k.clone()], v.to_toml()));
+            }
+            for (k, v) in &self.alias {
+                entries.push((vec!["alias".into(), k.clone()], v.as_str().into()));
+            }
+            entries
+        }
+
+        /// Writes condep owned keys into `doc` keeping the rest of it (including comments and formatting) untouched
+        pub fn merge_into(&self, doc: &mut Document) {
+            let entries = self.managed_entries();
+            remove_stale(doc.as_table_mut(), &mut Vec::new(), &entries);
+            for (path, value) in entries {
+                set_managed(doc.as_table_mut(), &path, value)
+            }
+        }
+
+        pub fn merge_into_str(&self, existing: &str) -> Result<String, toml_edit::TomlError> {
+            existing
+                .parse::<Document>()
+                .map(|mut doc| { self.merge_into(&mut doc); doc.to_string() })
+        }
     } 
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
 
-    #[derive(Serialize, Deserialize, Debug)]
-    pub struct Package {
-        pub name: String,
+    use std::path::Path;
+
+    use super::{apply_sources, BuildConfiguration, EnvPair, LogLevel, ResolvedBuild, RunnerMode, ValueAlternatives, VarAction};
+    use super::toml::{Build, Config, EnvValue, MANAGED_MARK};
+
+    fn resolved(rustflags: &[&str]) -> ResolvedBuild {
+        ResolvedBuild {
+            target: Some("armv7-unknown-linux-gnueabi".into()),
+            env: vec![],
+            linker: None,
+            rustflags: rustflags.iter().map(|f| f.to_string()).collect(),
+            lib_search_paths: vec![],
+            sysroot: None
+        }
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
-    pub struct Cargo {
-        pub package: Package,
+    #[test]
+    fn no_rustflags_leave_the_user_ones_alone() {
+        assert_eq!(resolved(&[]).encoded_rustflags(None, Some("-C opt-level=2".into())), None);
+    }
+
+    #[test]
+    fn rustflags_are_appended_to_the_user_ones() {
+        let build = resolved(&["-L", "/sdk/lib"]);
+        assert_eq!(build.encoded_rustflags(None, None).unwrap(), "-L\x1f/sdk/lib");
+        assert_eq!(build.encoded_rustflags(None, Some(" -C  opt-level=2 ".into())).unwrap(), "-C\x1fopt-level=2\x1f-L\x1f/sdk/lib");
+        // the encoded form wins over RUSTFLAGS like in cargo
+        assert_eq!(build.encoded_rustflags(Some("--cfg\x1fx".into()), Some("-C opt-level=2".into())).unwrap(), "--cfg\x1fx\x1f-L\x1f/sdk/lib");
+        assert_eq!(build.encoded_rustflags(Some(String::new()), None).unwrap(), "-L\x1f/sdk/lib");
+    }
+
+    fn generated() -> Config {
+        Config {
+            alias: BTreeMap::from([("deploy".into(), "condep deploy".into())]),
+            build: Build::with_target("armv7-unknown-linux-gnueabi".into(), vec!["-L".into(), "/sdk/lib".into()]),
+            env: BTreeMap::from([("CC".into(), EnvValue::from("arm-gcc".to_string()))]),
+            target: Config::target_mono("armv7-unknown-linux-gnueabi".into(), Config::LINKER.into(), "arm-g++".into())
+        }
+    }
+
+    #[test]
+    fn parse_accepts_every_shape_cargo_accepts() {
+        let config = Config::parse(r#"
+            [alias]
+            b = ["build", "--release"]
+            d = "condep deploy"
+            [build]
+            target = "armv7-unknown-linux-gnueabi"
+            rustflags = "-C target-cpu=native"
+            [env]
+            PLAIN = "1"
+            FORCED = { value = "2", force = true }
+            [target.armv7-unknown-linux-gnueabi]
+            linker = "arm-g++"
+            [unrelated]
+            key = 1
+        "#).unwrap();
+
+        assert_eq!(config.alias["b"], "build --release");
+        assert_eq!(config.alias["d"], "condep deploy");
+        assert_eq!(config.build.target.as_deref(), Some("armv7-unknown-linux-gnueabi"));
+        assert_eq!(config.build.rustflags, ["-C", "target-cpu=native"]);
+        assert_eq!(config.env["PLAIN"], EnvValue::Plain("1".into()));
+        assert_eq!(config.env["FORCED"], EnvValue::Detailed { value: "2".into(), force: true, relative: false });
+        assert_eq!(config.target_val("armv7-unknown-linux-gnueabi", Config::LINKER), Some("arm-g++"));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_toml() {
+        assert!(Config::parse("[build").is_err());
     }
-}
 
+    #[test]
+    fn merge_keeps_hand_written_content() {
+        let existing = "# my settings\n[build]\njobs = 4 # keep\n\n[alias]\nb = [\"build\", \"--release\"]\n";
+        let merged = generated().merge_into_str(existing).unwrap();
+
+        assert!(merged.contains("# my settings"));
+        assert!(merged.contains("jobs = 4 # keep"));
+        assert!(merged.contains("b = [\"build\", \"--release\"]"));
+        assert!(merged.contains(&format!("target = \"armv7-unknown-linux-gnueabi\" {}", MANAGED_MARK)));
+        assert!(merged.contains(&format!("linker = \"arm-g++\" {}", MANAGED_MARK)));
+        assert!(merged.contains(&format!("CC = \"arm-gcc\" {}", MANAGED_MARK)));
+    }
+
+    #[test]
+    fn merge_updates_marked_values_and_removes_stale_ones() {
+        let first = generated().merge_into_str("").unwrap();
+
+        let mut second = generated();
+        second.env = BTreeMap::from([("CXX".into(), EnvValue::from("arm-g++".to_string()))]);
+        second.target = Config::target_mono("aarch64-unknown-linux-gnu".into(), Config::LINKER.into(), "aarch64-g++".into());
+        second.build = Build::with_target("aarch64-unknown-linux-gnu".into(), vec![]);
+        let merged = second.merge_into_str(&first).unwrap();
+
+        assert!(!merged.contains("CC ="));
+        assert!(!merged.contains("armv7"));
+        assert!(merged.contains("CXX = \"arm-g++\""));
+        assert!(merged.contains("target = \"aarch64-unknown-linux-gnu\""));
+        assert_eq!(Config::parse(&merged).unwrap().target_val("aarch64-unknown-linux-gnu", Config::LINKER), Some("aarch64-g++"));
+    }
 
+    #[test]
+    fn merge_replaces_unmarked_values_of_owned_keys() {
+        let merged = generated().merge_into_str("[env]\nCC = \"gcc\"\nOTHER = \"x\"\n").unwrap();
+        let parsed = Config::parse(&merged).unwrap();
 
+        assert_eq!(parsed.env["CC"].value(), "arm-gcc");
+        assert_eq!(parsed.env["OTHER"].value(), "x");
+    }
+
+    #[test]
+    fn merge_without_rustflags_keeps_the_user_ones() {
+        let existing = "[build]\nrustflags = [\"-C\", \"target-cpu=native\"]\n";
+        let mut no_flags = generated();
+        no_flags.build.rustflags = vec![];
+        let merged = no_flags.merge_into_str(existing).unwrap();
+        assert_eq!(Config::parse(&merged).unwrap().build.rustflags, ["-C", "target-cpu=native"]);
+
+        let managed = generated().merge_into_str(existing).unwrap();
+        assert_eq!(Config::parse(&managed).unwrap().build.rustflags, ["-L", "/sdk/lib"]);
+        // a managed value is removed once there are no flags to write
+        assert!(Config::parse(&no_flags.merge_into_str(&managed).unwrap()).unwrap().build.rustflags.is_empty());
+    }
+
+    #[test]
+    fn process_env_keeps_set_variables_unless_forced() {
+        std::env::set_var("CONDEP_TEST_PLAIN", "old");
+        std::env::set_var("CONDEP_TEST_FORCED", "old");
+        std::env::remove_var("CONDEP_TEST_RELATIVE");
+        std::env::remove_var("CONDEP_TEST_UNSET");
+        let config = Config::parse(r#"
+            [env]
+            CONDEP_TEST_PLAIN = "new"
+            CONDEP_TEST_FORCED = { value = "new", force = true }
+            CONDEP_TEST_RELATIVE = { value = "assets", relative = true }
+            CONDEP_TEST_UNSET = "new"
+        "#).unwrap();
+
+        let env: BTreeMap<String, String> = config.process_env(Path::new("/project")).into_iter().collect();
+        assert_eq!(env.get("CONDEP_TEST_PLAIN"), None);
+        assert_eq!(env["CONDEP_TEST_FORCED"], "new");
+        assert_eq!(env["CONDEP_TEST_RELATIVE"], "/project/assets");
+        assert_eq!(env["CONDEP_TEST_UNSET"], "new");
+    }
 
+    #[test]
+    fn run_env_sees_variables_set_by_sources() {
+        let dir = std::env::temp_dir().join(format!("condep-run-env-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("plugins")).unwrap();
+        let script = dir.join("env.sh");
+        std::fs::write(&script, format!("export CONDEP_TEST_TOOLCHAIN={}\n", dir.display())).unwrap();
+        std::env::remove_var("CONDEP_TEST_TOOLCHAIN");
+
+        let pair = |key: &str, value: &str| EnvPair { key: key.into(), value: ValueAlternatives::new(vec![value.into()], VarAction::Set) };
+        let build = BuildConfiguration::new(vec![], vec![script.to_str().unwrap().into()], vec![], None, vec![])
+            .with_run_env(vec![
+                pair("CONDEP_TEST_PLUGINS", "$CONDEP_TEST_TOOLCHAIN/plugins"),
+                pair("CONDEP_TEST_MISSING", "$CONDEP_TEST_TOOLCHAIN/missing"),
+            ]);
+        let plugins = dir.join("plugins").to_string_lossy().into_owned();
+
+        assert_eq!(build.apply_run_env(&|s: &String| Path::new(s).exists()), [
+            ("CONDEP_TEST_PLUGINS".to_string(), Some(plugins.clone())),
+            ("CONDEP_TEST_MISSING".to_string(), None),
+        ]);
+        assert_eq!(std::env::var("CONDEP_TEST_PLUGINS").unwrap(), plugins);
+        assert!(std::env::var_os("CONDEP_TEST_MISSING").is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_sources_do_not_abort_run_env() {
+        let missing = std::env::temp_dir().join(format!("condep-no-such-source-{}.sh", std::process::id()));
+        assert!(apply_sources(&[missing.to_str().unwrap().into()], &LogLevel::Off).is_err());
+
+        let build = BuildConfiguration::new(vec![], vec![missing.to_str().unwrap().into()], vec![], None, vec![]);
+        assert!(build.apply_run_env(&|_: &String| true).is_empty());
+
+        let build = build.with_run_env(vec![EnvPair { key: "CONDEP_TEST_RUN_ENV".into(), value: ValueAlternatives::new(vec!["/".into()], VarAction::Set) }]);
+        assert_eq!(build.apply_run_env(&|_: &String| true), [("CONDEP_TEST_RUN_ENV".to_string(), Some("/".to_string()))]);
+    }
+
+    #[test]
+    fn runner_modes_are_parsed_strictly() {
+        assert_eq!("host".parse::<RunnerMode>(), Ok(RunnerMode::Host));
+        assert_eq!("remote".parse::<RunnerMode>(), Ok(RunnerMode::Remote));
+        assert_eq!("qemu".parse::<RunnerMode>(), Ok(RunnerMode::Qemu));
+        assert!("Qemu".parse::<RunnerMode>().is_err());
+        assert!("".parse::<RunnerMode>().is_err());
+    }
+
+    #[test]
+    fn runner_keeps_a_sysroot_with_spaces_in_one_argument() {
+        assert_eq!(RunnerMode::Remote.command(Some(Path::new("/sdk"))), ["cargo", "condep", "run", "--remote"]);
+        assert_eq!(RunnerMode::Qemu.command(None), ["cargo", "condep", "run", "--qemu"]);
+        assert_eq!(
+            RunnerMode::Qemu.command(Some(Path::new("/opt/My SDK/sysroot"))),
+            ["cargo", "condep", "run", "--qemu", "--sysroot", "/opt/My SDK/sysroot"]
+        );
+
+        let mut build = resolved(&[]);
+        build.sysroot = Some("/opt/My SDK/sysroot".into());
+        let merged = build
+            .to_config_toml(BTreeMap::new(), Some(RunnerMode::Qemu))
+            .merge_into_str("[target.armv7-unknown-linux-gnueabi]\nrunner = \"cargo condep run --remote\"\n")
+            .unwrap();
+        assert!(merged.contains(&format!(
+            "runner = [\"cargo\", \"condep\", \"run\", \"--qemu\", \"--sysroot\", \"/opt/My SDK/sysroot\"] {}",
+            MANAGED_MARK
+        )));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{path::{Path, PathBuf}, process::Command};
+
+use serde_json::{json, Value};
+
+use crate::deploy::{CallRemote, DeployError, DeployResult, ErrorKind, shell_quote};
+
+/// Port `gdbserver` listens on unless configured otherwise
+pub const DEFAULT_PORT: u16 = 2345;
+/// Output of `gdbserver` on the device
+pub const GDBSERVER_LOG: &str = "/tmp/condep-gdbserver.log";
+
+/// Starts `gdbserver` for `exe` in the background on the device and returns its pid.
+/// It serves a single connection and exits with the debugged program
+pub fn start_gdbserver<R: CallRemote + ?Sized>(remote: &mut R, exe: &Path, args: &[String], port: u16) -> DeployResult<u32> {
+    let exe = shell_quote(&exe.to_string_lossy());
+    let args: Vec<String> = args.iter().map(|a| shell_quote(a)).collect();
+    let log = shell_quote(GDBSERVER_LOG);
+    // give gdbserver a moment to fail on a busy port or a missing binary
+    let cmd = format!(
+        "nohup gdbserver --once :{port} {exe} {args} </dev/null >{log} 2>&1 & pid=$!; sleep 1; if kill -0 $pid 2>/dev/null; then echo $pid; else cat {log} >&2; exit 1; fi",
+        port = port,
+        exe = exe,
+        args = args.join(" "),
+        log = log
+    );
+    let out = remote.call_remote(cmd.as_bytes())?.check(cmd.as_bytes())?;
+    out.stdout_str().trim().parse().map_err(|err| DeployError::new(ErrorKind::RemoteStatus, Box::new(format!("gdbserver pid: {}", err))))
+}
+
+/// Host side of a remote debugging session
+#[derive(Debug, Clone)]
+pub struct GdbSession {
+    /// Cross gdb
+    pub gdb: PathBuf,
+    /// Local copy of the debugged executable, with debug info
+    pub program: PathBuf,
+    /// `host:port` of gdbserver
+    pub address: String,
+    /// Target root gdb loads shared libraries from
+    pub sysroot: Option<PathBuf>,
+    /// Directories with local copies of libraries deployed along with the program
+    pub solib_search_path: Vec<PathBuf>
+}
+
+impl GdbSession {
+    /// Commands run by gdb before the session starts
+    pub fn setup_commands(&self) -> Vec<String> {
+        let mut commands = Vec::new();
+        if let Some(sysroot) = &self.sysroot {
+            commands.push(format!("set sysroot {}", sysroot.display()));
+        }
+        if !self.solib_search_path.is_empty() {
+            let paths = std::env::join_paths(&self.solib_search_path).unwrap_or_default();
+            commands.push(format!("set solib-search-path {}", paths.to_string_lossy()));
+        }
+        commands
+    }
+
+    /// gdb attached to the gdbserver
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.gdb);
+        for c in self.setup_commands() {
+            cmd.arg("-ex").arg(c);
+        }
+        cmd.arg("-ex").arg(format!("target remote {}", self.address)).arg(&self.program);
+        cmd
+    }
+
+    /// VS Code `cppdbg` configuration attaching to the gdbserver
+    pub fn launch_config(&self, name: &str) -> Value {
+        json!({
+            "name": name,
+            "type": "cppdbg",
+            "request": "launch",
+            "program": self.program,
+            "cwd": "${workspaceFolder}",
+            "MIMode": "gdb",
+            "miDebuggerPath": self.gdb,
+            "miDebuggerServerAddress": self.address,
+            "setupCommands": self.setup_commands().into_iter().map(|text| json!({ "text": text })).collect::<Vec<_>>()
+        })
+    }
+
+    /// Adds the configuration to `launch.json` at `path` or replaces the one with the same name
+    pub fn write_launch_json(&self, path: &Path, name: &str) -> Result<(), String> {
+        let mut launch = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice::<Value>(&bytes).map_err(|err| format!("{:?}: {}. Comments are not supported", path, err))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => json!({ "version": "0.2.0", "configurations": [] }),
+            Err(err) => return Err(format!("{:?}: {}", path, err))
+        };
+        let configurations = launch
+            .as_object_mut()
+            .and_then(|l| l.entry("configurations").or_insert_with(|| json!([])).as_array_mut())
+            .ok_or_else(|| format!("{:?}: `configurations` is not an array", path))?;
+        let config = self.launch_config(name);
+        match configurations.iter_mut().find(|c| c.get("name").and_then(Value::as_str) == Some(name)) {
+            Some(existing) => *existing = config,
+            None => configurations.push(config)
+        }
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| format!("{:?}: {}", dir, err))?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(&launch).unwrap()).map_err(|err| format!("{:?}: {}", path, err))
+    }
+}
@@ -1,4 +1,4 @@
-use std::{path::{Path, PathBuf}, fmt::{Display, Debug}};
+use std::{path::{Path, PathBuf}, fmt::{Display, Debug}, borrow::Cow, collections::{BTreeMap, BTreeSet}};
 
 use serde::{Serialize, Deserialize};
 
@@ -9,7 +9,49 @@ pub struct DeployPaths {
     pub execs: Vec<PathBuf>,
     pub libs: Vec<PathBuf>,
     pub config_files: Vec<PathBuf>,
-    pub user_files: Vec<PathBuf>
+    pub user_files: Vec<PathBuf>,
+    /// Destination subdirectory (relative to the kind directory) of sources which are not deployed directly into it
+    pub subdirs: BTreeMap<PathBuf, PathBuf>,
+    /// Sources deployed as symlinks, with their targets
+    pub symlinks: BTreeMap<PathBuf, PathBuf>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    Exec,
+    Lib,
+    Config,
+    User
+}
+
+impl Display for FileKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            FileKind::Exec => "exec",
+            FileKind::Lib => "lib",
+            FileKind::Config => "config",
+            FileKind::User => "user",
+        })
+    }
+}
+
+impl DeployPaths {
+    pub fn get_mut(&mut self, kind: FileKind) -> &mut Vec<PathBuf> {
+        match kind {
+            FileKind::Exec => &mut self.execs,
+            FileKind::Lib => &mut self.libs,
+            FileKind::Config => &mut self.config_files,
+            FileKind::User => &mut self.user_files,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (FileKind, &PathBuf)> {
+        self.execs.iter().map(|p| (FileKind::Exec, p))
+            .chain(self.libs.iter().map(|p| (FileKind::Lib, p)))
+            .chain(self.config_files.iter().map(|p| (FileKind::Config, p)))
+            .chain(self.user_files.iter().map(|p| (FileKind::User, p)))
+    }
 }
 
 
@@ -18,12 +60,230 @@ pub struct DeployConfig {
     pub execs_path: PathBuf,
     pub libs_path: PathBuf,
     pub config_path: PathBuf,
-    pub user_path: PathBuf
+    pub user_path: PathBuf,
+    /// Where to keep a copy of the deploy manifest on the device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "ExtraFiles::is_empty")]
+    pub files: ExtraFiles,
+    /// Where the device keeps system libraries. Dependencies found there (or in `libs_path`) are not deployed
+    #[serde(default = "default_device_lib_paths")]
+    pub device_lib_paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub permissions: KindPermissions,
+    /// Per file permissions keyed by destination path or file name. Override `permissions` field by field
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overrides: BTreeMap<String, FilePermissions>,
+    /// Strip executables and libraries before upload. Debug info is kept locally
+    #[serde(default)]
+    pub strip: StripMode
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StripMode {
+    /// Deploy artifacts as built
+    #[default]
+    None,
+    /// Remove debug sections
+    Debug,
+    /// Remove debug sections and symbols not needed for relocations
+    All
+}
+
+fn default_device_lib_paths() -> Vec<PathBuf> {
+    vec![PathBuf::from("/lib"), PathBuf::from("/usr/lib")]
+}
+
+/// Glob patterns of additional files per kind, relative to the package root (e.g. `assets/**/*.qml`).
+/// Directory structure below the non-glob part of the pattern is kept under the kind directory
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ExtraFiles {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub execs: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub libs: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub config: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub user: Vec<String>,
+    /// Symlinks met in matched files and directories
+    pub symlinks: SymlinkPolicy
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Deploy the file or directory the link points to
+    #[default]
+    Follow,
+    /// Create the same link on the device
+    Preserve,
+    Skip
+}
+
+/// Leading components of `pattern` without glob metacharacters
+fn glob_base(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+        .collect()
+}
+
+impl ExtraFiles {
+    pub fn is_empty(&self) -> bool { self.patterns().next().is_none() }
+
+    fn patterns(&self) -> impl Iterator<Item = (FileKind, &String)> {
+        self.execs.iter().map(|p| (FileKind::Exec, p))
+            .chain(self.libs.iter().map(|p| (FileKind::Lib, p)))
+            .chain(self.config.iter().map(|p| (FileKind::Config, p)))
+            .chain(self.user.iter().map(|p| (FileKind::User, p)))
+    }
+
+    /// Adds files matching the patterns to `paths`. Matched directories are added with all their contents
+    pub fn collect(&self, root: &Path, paths: &mut DeployPaths) -> DeployResult<()> {
+        for (kind, pattern) in self.patterns() {
+            let pattern = root.join(pattern);
+            // a plain path keeps its own name under the kind directory
+            let base = match glob_base(&pattern) {
+                base if base == pattern => base.parent().map(Path::to_path_buf).unwrap_or(base),
+                base => base
+            };
+            let matches = glob::glob(&pattern.to_string_lossy())
+                .map_err(|err| DeployError::new_copy_err(Box::new(format!("{:?}: {}", pattern, err))))?;
+            for path in matches {
+                let path = path.map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
+                self.add_tree(kind, &path, &base, paths, &mut BTreeSet::new())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `visited` holds canonical directories on the current path to break symlink cycles
+    fn add_tree(&self, kind: FileKind, path: &Path, base: &Path, paths: &mut DeployPaths, visited: &mut BTreeSet<PathBuf>) -> DeployResult<()> {
+        let io_err = |err: std::io::Error| DeployError::new_copy_err(Box::new(format!("{:?}: {}", path, err)));
+        let meta = std::fs::symlink_metadata(path).map_err(io_err)?;
+
+        let is_dir = if meta.file_type().is_symlink() {
+            match self.symlinks {
+                SymlinkPolicy::Skip => return Ok(()),
+                SymlinkPolicy::Preserve => {
+                    let target = std::fs::read_link(path).map_err(io_err)?;
+                    paths.symlinks.insert(path.to_path_buf(), target);
+                    false
+                },
+                SymlinkPolicy::Follow => std::fs::metadata(path).map_err(io_err)?.is_dir(),
+            }
+        } else {
+            meta.is_dir()
+        };
+
+        if is_dir {
+            let canonical = path.canonicalize().map_err(io_err)?;
+            if !visited.insert(canonical.clone()) {
+                return Ok(());
+            }
+            let mut entries = std::fs::read_dir(path)
+                .and_then(|dir| dir.map(|e| e.map(|e| e.path())).collect::<Result<Vec<_>, _>>())
+                .map_err(io_err)?;
+            entries.sort();
+            for entry in entries {
+                self.add_tree(kind, &entry, base, paths, visited)?;
+            }
+            visited.remove(&canonical);
+            return Ok(());
+        }
+
+        let subdir = path
+            .parent()
+            .and_then(|parent| parent.strip_prefix(base).ok())
+            .filter(|subdir| !subdir.as_os_str().is_empty())
+            .map(Path::to_path_buf);
+        if let Some(subdir) = subdir {
+            paths.subdirs.insert(path.to_path_buf(), subdir);
+        }
+        paths.get_mut(kind).push(path.to_path_buf());
+        Ok(())
+    }
+}
+
+/// Mode and ownership of deployed files. Unset fields are left to the default (mode) or the device (owner, group)
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilePermissions {
+    #[serde(default, with = "octal::option", skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>
+}
+
+impl FilePermissions {
+    pub fn with_mode(mode: u32) -> Self {
+        FilePermissions { mode: Some(mode), ..Default::default() }
+    }
+
+    /// Fields set in `top` replace fields of `self`
+    pub fn overridden(&self, top: &FilePermissions) -> FilePermissions {
+        FilePermissions {
+            mode: top.mode.or(self.mode),
+            owner: top.owner.clone().or_else(|| self.owner.clone()),
+            group: top.group.clone().or_else(|| self.group.clone())
+        }
+    }
+}
+
+/// Permissions for every file kind
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct KindPermissions {
+    pub execs: FilePermissions,
+    pub libs: FilePermissions,
+    pub config: FilePermissions,
+    pub user: FilePermissions
+}
+
+impl Default for KindPermissions {
+    fn default() -> Self {
+        KindPermissions {
+            execs: FilePermissions::with_mode(DeployConfig::default_mode(FileKind::Exec)),
+            libs: FilePermissions::with_mode(DeployConfig::default_mode(FileKind::Lib)),
+            config: FilePermissions::with_mode(DeployConfig::default_mode(FileKind::Config)),
+            user: FilePermissions::with_mode(DeployConfig::default_mode(FileKind::User))
+        }
+    }
+}
+
+impl KindPermissions {
+    pub fn get(&self, kind: FileKind) -> &FilePermissions {
+        match kind {
+            FileKind::Exec => &self.execs,
+            FileKind::Lib => &self.libs,
+            FileKind::Config => &self.config,
+            FileKind::User => &self.user,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum ErrorKind {
-    CopyFiles
+    CopyFiles,
+    Connect,
+    /// Server key is unknown or does not match known_hosts
+    HostKey,
+    /// Can not run remote command
+    RemoteCall,
+    /// Remote command exited with non-zero status
+    RemoteStatus,
+    /// Can not read or write the local record of deploys to a device
+    History,
+    /// Binary is built for another architecture than the target or the device
+    Arch,
+    /// Can not strip an artifact
+    Strip,
+    /// No device is selected
+    NoDevice
 }
 
 pub struct DeployError {
@@ -52,42 +312,390 @@ impl DeployError {
     pub fn new_copy_err(cause: Box<dyn Display>) -> Self {
         return DeployError { kind: ErrorKind::CopyFiles, cause: cause };
     }
+    pub fn new_remote_call_err(cause: Box<dyn Display>) -> Self {
+        DeployError { kind: ErrorKind::RemoteCall, cause }
+    }
+    pub fn kind(&self) -> &ErrorKind { &self.kind }
+
+    /// Prepends `context` to the cause keeping the kind
+    pub fn context(self, context: String) -> Self {
+        let cause = format!("{}: {}", context, self.cause);
+        DeployError { kind: self.kind, cause: Box::new(cause) }
+    }
 }
 
 pub type DeployResult<T> = std::result::Result<T, DeployError>;
 
+/// File modes as octal strings (`"644"`). Numbers are read as octal digits too (`755`)
+pub mod octal {
+    use serde::{Serializer, Deserializer, Deserialize, de::Error};
+
+    pub fn serialize<S: Serializer>(mode: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:o}", mode))
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Mode {
+        Str(String),
+        Num(u32)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        let s = match Mode::deserialize(deserializer)? {
+            Mode::Str(s) => s,
+            Mode::Num(n) => n.to_string(),
+        };
+        u32::from_str_radix(s.trim_start_matches("0o"), 8)
+            .map_err(|err| D::Error::custom(format!("bad file mode {:?}: {}", s, err)))
+    }
+
+    pub mod option {
+        use serde::{Serializer, Deserializer};
+
+        pub fn serialize<S: Serializer>(mode: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error> {
+            match mode {
+                Some(mode) => super::serialize(mode, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+            super::deserialize(deserializer).map(Some)
+        }
+    }
+}
+
+/// One file to copy
+#[derive(Serialize, Debug, Clone)]
+pub struct PlanEntry {
+    pub kind: FileKind,
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    pub size: u64,
+    #[serde(with = "octal")]
+    pub mode: u32,
+    /// Changed on the device after upload if set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Created as a symlink to this target instead of copying `src`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<PathBuf>,
+    /// Whether `dst` already exists. `None` if it can not be known without connecting to the device
+    pub overwrites: Option<bool>
+}
+
+/// Remote command with the deploy stage it runs at
+#[derive(Serialize, Debug, Clone)]
+pub struct PlannedCommand {
+    pub stage: &'static str,
+    pub cmd: String
+}
+
+/// Everything deploy would do, built before touching the device
+#[derive(Serialize, Debug, Default)]
+pub struct DeployPlan {
+    /// Human readable device description (e.g. `root@192.168.205.1`)
+    pub target: String,
+    pub files: Vec<PlanEntry>,
+    pub commands: Vec<PlannedCommand>
+}
+
+/// Destination paths of `entries` grouped by kind
+pub fn destinations(entries: &[PlanEntry]) -> DeployPaths {
+    let mut result = DeployPaths::default();
+    for entry in entries {
+        match entry.kind {
+            FileKind::Exec => result.execs.push(entry.dst.clone()),
+            FileKind::Lib => result.libs.push(entry.dst.clone()),
+            FileKind::Config => result.config_files.push(entry.dst.clone()),
+            FileKind::User => result.user_files.push(entry.dst.clone()),
+        }
+    }
+    result
+}
+
+impl Display for DeployPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Deploy plan for {}", self.target)?;
+        for entry in &self.files {
+            let overwrites = match entry.overwrites {
+                Some(true) => "overwrite",
+                Some(false) => "new",
+                None => "?",
+            };
+            let owner = match (&entry.owner, &entry.group) {
+                (None, None) => String::new(),
+                (owner, group) => format!(", {}:{}", owner.as_deref().unwrap_or(""), group.as_deref().unwrap_or("")),
+            };
+            match &entry.link {
+                Some(target) => writeln!(f, "  {:<6} {} -> {} (symlink to {}, {})", entry.kind, entry.src.display(), entry.dst.display(), target.display(), overwrites)?,
+                None => writeln!(f, "  {:<6} {} -> {} ({} bytes, {:o}{}, {})", entry.kind, entry.src.display(), entry.dst.display(), entry.size, entry.mode, owner, overwrites)?,
+            }
+        }
+        for cmd in &self.commands {
+            writeln!(f, "  {:<12} {}", cmd.stage, cmd.cmd)?;
+        }
+        write!(f, "  {} files, {} bytes", self.files.len(), self.files.iter().map(|e| e.size).sum::<u64>())
+    }
+}
+
 impl DeployConfig {
-    pub fn copy_files<F>(self, src: DeployPaths, f: &mut F) -> DeployResult<DeployPaths>
-    where
-        F: FnMut(&Path, &Path) -> DeployResult<PathBuf> {
-            let mut result: DeployPaths = DeployPaths::default();
-            for exe in src.execs { result.execs.push(f(exe.as_path(), self.execs_path.as_path())?) }
-            for lib in src.libs { result.libs.push(f(lib.as_path(), self.libs_path.as_path())?) }
-            for cfg in src.config_files { result.config_files.push(f(cfg.as_path(), self.config_path.as_path())?) }
-            for usr in src.user_files { result.user_files.push(f(usr.as_path(), self.user_path.as_path())?) }
-            Ok(result)
+    pub const DEFAULT_MODE: u32 = 0o644;
+    pub const EXEC_MODE: u32 = 0o755;
+
+    /// Mode of `kind` files without a configured one
+    pub fn default_mode(kind: FileKind) -> u32 {
+        match kind {
+            FileKind::Exec => Self::EXEC_MODE,
+            _ => Self::DEFAULT_MODE,
+        }
+    }
+
+    pub fn dir(&self, kind: FileKind) -> &Path {
+        match kind {
+            FileKind::Exec => &self.execs_path,
+            FileKind::Lib => &self.libs_path,
+            FileKind::Config => &self.config_path,
+            FileKind::User => &self.user_path,
+        }
+    }
+
+    /// Permissions of `kind` with the override of `dst` applied
+    pub fn permissions(&self, kind: FileKind, dst: &Path) -> FilePermissions {
+        let base = self.permissions.get(kind);
+        let file_name = dst.file_name().map(|n| n.to_string_lossy());
+        self.overrides
+            .iter()
+            .find(|(key, _)| Path::new(key) == dst || Some(key.as_str()) == file_name.as_deref())
+            .map(|(_, top)| base.overridden(top))
+            .unwrap_or_else(|| base.clone())
+    }
+
+    /// Maps every source file to its destination without copying anything
+    pub fn plan(&self, src: &DeployPaths) -> DeployResult<Vec<PlanEntry>> {
+        src
+            .iter()
+            .map(|(kind, path)| {
+                let link = src.symlinks.get(path).cloned();
+                let size = match link {
+                    Some(_) => 0,
+                    None => std::fs::metadata(path)
+                        .map_err(|err| DeployError::new_copy_err(Box::new(format!("{:?}: {}", path, err))))?
+                        .len(),
+                };
+                let dir = match src.subdirs.get(path) {
+                    Some(subdir) => self.dir(kind).join(subdir),
+                    None => self.dir(kind).to_path_buf(),
+                };
+                let dst = dir.join(path.file_name().unwrap());
+                let permissions = self.permissions(kind, &dst);
+                Ok(PlanEntry {
+                    kind,
+                    src: path.clone(),
+                    dst,
+                    size,
+                    mode: permissions.mode.unwrap_or_else(|| Self::default_mode(kind)),
+                    owner: permissions.owner,
+                    group: permissions.group,
+                    link,
+                    overwrites: None
+                })
+            })
+            .collect()
+    }
+}
+
+/// Quotes `s` for a POSIX shell
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// What deploy actually transferred
+#[derive(Debug, Default)]
+pub struct DeployReport {
+    pub uploaded_files: usize,
+    pub uploaded_bytes: u64,
+    pub skipped_files: usize,
+    pub skipped_bytes: u64
+}
+
+impl DeployReport {
+    pub fn new(uploaded: &[PlanEntry], skipped: &[PlanEntry]) -> Self {
+        DeployReport {
+            uploaded_files: uploaded.len(),
+            uploaded_bytes: uploaded.iter().map(|e| e.size).sum(),
+            skipped_files: skipped.len(),
+            skipped_bytes: skipped.iter().map(|e| e.size).sum()
+        }
+    }
+}
+
+impl Display for DeployReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} files ({} bytes) uploaded, {} unchanged ({} bytes) skipped",
+            self.uploaded_files, self.uploaded_bytes, self.skipped_files, self.skipped_bytes)
     }
 }
 
 pub trait Deploy {
-    fn deploy(&mut self, src: DeployPaths, conf: DeployConfig) -> DeployResult<DeployPaths>;
+    /// Copies every `entry.src` to `entry.dst`
+    fn deploy(&mut self, files: &[PlanEntry]) -> DeployResult<()>;
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RemoteOutput {
+    /// -1 if the remote side did not report it
+    pub status: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>
+}
+
+impl RemoteOutput {
+    pub fn success(&self) -> bool { self.status == 0 }
+
+    pub fn stdout_str(&self) -> Cow<'_, str> { String::from_utf8_lossy(&self.stdout) }
+
+    pub fn stderr_str(&self) -> Cow<'_, str> { String::from_utf8_lossy(&self.stderr) }
+
+    /// Converts non-zero exit status to `ErrorKind::RemoteStatus` error
+    pub fn check(self, cmd: &[u8]) -> DeployResult<Self> {
+        if self.success() {
+            Ok(self)
+        } else {
+            Err(DeployError::new(ErrorKind::RemoteStatus, Box::new(format!(
+                "`{}` exited with status {}: {}",
+                String::from_utf8_lossy(cmd),
+                self.status,
+                self.stderr_str().trim_end()
+            ))))
+        }
+    }
 }
 
 pub trait CallRemote {
-    fn call_remote(&mut self, cmd: &[u8]) -> DeployResult<()>;
+    fn call_remote(&mut self, cmd: &[u8]) -> DeployResult<RemoteOutput>;
+
+    /// Forwards output to the terminal while the command runs. Returns exit status
+    fn call_remote_streaming(&mut self, cmd: &[u8]) -> DeployResult<i32>;
+
+    /// How a destination path returned by `Deploy::deploy` is addressed in commands
+    fn remote_path(&self, path: &Path) -> PathBuf { path.to_path_buf() }
 }
 
 
+/// Deploys nowhere. Every operation fails with `ErrorKind::NoDevice`
 pub struct Noop {}
 
+impl Noop {
+    fn err() -> DeployError {
+        DeployError::new(ErrorKind::NoDevice, Box::new("no deploy method selected"))
+    }
+}
+
 impl Deploy for Noop {
-    fn deploy(&mut self, _: DeployPaths, _: DeployConfig) -> DeployResult<DeployPaths> {
-        unimplemented!()
+    fn deploy(&mut self, _: &[PlanEntry]) -> DeployResult<()> {
+        Err(Self::err())
     }
 }
 
 impl CallRemote for Noop {
-    fn call_remote(&mut self, _: &[u8]) -> DeployResult<()> {
-        unimplemented!()
+    fn call_remote(&mut self, _: &[u8]) -> DeployResult<RemoteOutput> {
+        Err(Self::err())
+    }
+
+    fn call_remote_streaming(&mut self, _: &[u8]) -> DeployResult<i32> {
+        Err(Self::err())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> DeployConfig {
+        let base = "execs_path: /bin\nlibs_path: /lib\nconfig_path: /etc\nuser_path: /home\n";
+        serde_yaml::from_str(&format!("{}{}", base, yaml)).unwrap()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn modes_are_octal() {
+        let mode = |yaml: &str| serde_yaml::from_str::<FilePermissions>(yaml).map(|p| p.mode);
+        assert_eq!(mode("mode: \"644\"").unwrap(), Some(0o644));
+        assert_eq!(mode("mode: \"0o750\"").unwrap(), Some(0o750));
+        assert_eq!(mode("mode: 755").unwrap(), Some(0o755));
+        assert_eq!(mode("owner: root").unwrap(), None);
+        assert!(mode("mode: \"689\"").is_err());
+        assert!(mode("mode: rwx").is_err());
+    }
+
+    #[test]
+    fn overrides_replace_kind_permissions_field_by_field() {
+        let config = config("permissions:\n  execs:\n    owner: root\n    group: root\noverrides:\n  tool:\n    mode: \"700\"\n  /bin/other:\n    group: video\n");
+        let tool = config.permissions(FileKind::Exec, Path::new("/bin/tool"));
+        assert_eq!(tool, FilePermissions { mode: Some(0o700), owner: Some("root".into()), group: Some("root".into()) });
+        let other = config.permissions(FileKind::Exec, Path::new("/bin/other"));
+        assert_eq!(other, FilePermissions { mode: None, owner: Some("root".into()), group: Some("video".into()) });
+        assert_eq!(config.permissions(FileKind::Lib, Path::new("/lib/tool")).mode, Some(0o700));
+        assert_eq!(config.permissions(FileKind::Lib, Path::new("/lib/libx.so")), FilePermissions::with_mode(0o644));
+    }
+
+    #[test]
+    fn plan_falls_back_to_the_mode_of_the_kind() {
+        let dir = std::env::temp_dir().join(format!("condep-deploy-plan-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (exec, lib) = (dir.join("tool"), dir.join("libx.so"));
+        std::fs::write(&exec, "exec").unwrap();
+        std::fs::write(&lib, "lib").unwrap();
+        let src = DeployPaths { execs: vec![exec], libs: vec![lib], ..Default::default() };
+
+        let plan = config("permissions:\n  execs:\n    owner: root\n  libs:\n    owner: root\n").plan(&src).unwrap();
+        assert_eq!(plan[0].dst, Path::new("/bin/tool"));
+        assert_eq!((plan[0].mode, plan[0].owner.as_deref()), (0o755, Some("root")));
+        assert_eq!((plan[1].mode, plan[1].owner.as_deref()), (0o644, Some("root")));
+        assert_eq!(plan[1].size, 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn glob_base_stops_at_the_first_pattern() {
+        assert_eq!(glob_base(Path::new("/p/assets/**/*.qml")), Path::new("/p/assets"));
+        assert_eq!(glob_base(Path::new("/p/config/app?.ini")), Path::new("/p/config"));
+        assert_eq!(glob_base(Path::new("/p/lib[0-9]/x.so")), Path::new("/p"));
+        assert_eq!(glob_base(Path::new("/p/plugins")), Path::new("/p/plugins"));
+    }
+
+    #[test]
+    fn extra_files_keep_the_structure_below_the_pattern_base() {
+        let root = std::env::temp_dir().join(format!("condep-deploy-files-{}", std::process::id()));
+        for file in ["assets/top.qml", "assets/qml/main.qml", "assets/qml/skip.txt", "plugins/a/b.so", "config/app.ini"] {
+            std::fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+            std::fs::write(root.join(file), file).unwrap();
+        }
+        let files = ExtraFiles {
+            user: vec!["assets/**/*.qml".into(), "plugins".into()],
+            config: vec!["config/*.ini".into()],
+            ..Default::default()
+        };
+        let mut paths = DeployPaths::default();
+        files.collect(&root, &mut paths).unwrap();
+
+        assert_eq!(paths.subdirs.get(&root.join("assets/qml/main.qml")), Some(&PathBuf::from("qml")));
+        assert_eq!(paths.subdirs.get(&root.join("assets/top.qml")), None);
+        assert_eq!(paths.subdirs.get(&root.join("plugins/a/b.so")), Some(&PathBuf::from("plugins/a")));
+        assert_eq!(paths.subdirs.get(&root.join("config/app.ini")), None);
+
+        let dsts: Vec<PathBuf> = config("").plan(&paths).unwrap().into_iter().map(|e| e.dst).collect();
+        assert_eq!(dsts, [
+            PathBuf::from("/etc/app.ini"),
+            PathBuf::from("/home/qml/main.qml"),
+            PathBuf::from("/home/top.qml"),
+            PathBuf::from("/home/plugins/a/b.so"),
+        ]);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
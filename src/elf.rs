@@ -0,0 +1,485 @@
+use std::{collections::{BTreeMap, BTreeSet}, fmt::Display, io::Read, path::{Path, PathBuf}};
+
+use goblin::elf::{Elf, header::{self, EM_386, EM_AARCH64, EM_ARM, EM_MIPS, EM_PPC, EM_PPC64, EM_RISCV, EM_X86_64}, section_header::SHN_UNDEF, symver::VER_FLG_BASE};
+
+use crate::deploy::{CallRemote, DeployError, DeployResult, ErrorKind, shell_quote};
+
+#[derive(Debug)]
+pub enum ElfError {
+    IOError(std::io::Error),
+    ParseError(goblin::error::Error)
+}
+
+impl std::fmt::Display for ElfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ElfError::IOError(err) => err.fmt(f),
+            ElfError::ParseError(err) => err.fmt(f),
+        }
+    }
+}
+
+pub fn read(path: &Path) -> Result<Vec<u8>, ElfError> {
+    std::fs::read(path).map_err(ElfError::IOError)
+}
+
+/// `DT_NEEDED` entries (sonames) of the ELF file at `path`
+pub fn needed(path: &Path) -> Result<Vec<String>, ElfError> {
+    let bytes = read(path)?;
+    let elf = Elf::parse(&bytes).map_err(ElfError::ParseError)?;
+    Ok(elf.libraries.iter().map(|l| l.to_string()).collect())
+}
+
+/// Shared libraries required by a set of artifacts, transitively
+#[derive(Debug, Default)]
+pub struct Dependencies {
+    /// soname -> file found in the search paths
+    pub found: BTreeMap<String, PathBuf>,
+    /// sonames not found in any search path
+    pub unresolved: BTreeSet<String>
+}
+
+/// Resolves `DT_NEEDED` of `roots` and of every library found on the way against `search_paths` in order.
+/// Files which are not ELF (e.g. scripts matched by `files` patterns) have no dependencies
+pub fn resolve_dependencies(roots: &[PathBuf], search_paths: &[PathBuf]) -> Result<Dependencies, ElfError> {
+    let mut result = Dependencies::default();
+    let provided: BTreeSet<String> = roots
+        .iter()
+        .filter_map(|p| p.file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+        .collect();
+
+    let mut queue: Vec<PathBuf> = roots.to_vec();
+    while let Some(path) = queue.pop() {
+        if ElfArch::of_file(&path)?.is_none() {
+            continue;
+        }
+        for soname in needed(&path)? {
+            if provided.contains(&soname) || result.found.contains_key(&soname) || result.unresolved.contains(&soname) {
+                continue;
+            }
+            match search_paths.iter().map(|dir| dir.join(&soname)).find(|p| p.is_file()) {
+                Some(lib) => {
+                    queue.push(lib.clone());
+                    result.found.insert(soname, lib);
+                },
+                None => { result.unresolved.insert(soname); }
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Subset of `sonames` present in any of `dirs` on the device
+pub fn present_on_device<R: CallRemote + ?Sized>(remote: &mut R, sonames: &BTreeSet<String>, dirs: &[PathBuf]) -> DeployResult<BTreeSet<String>> {
+    if sonames.is_empty() || dirs.is_empty() {
+        return Ok(BTreeSet::new());
+    }
+    let dirs = dirs.iter().map(|d| shell_quote(&d.to_string_lossy())).collect::<Vec<_>>().join(" ");
+    let names = sonames.iter().map(|n| shell_quote(n)).collect::<Vec<_>>().join(" ");
+    let cmd = format!(
+        "for n in {}; do for d in {}; do if [ -e \"$d/$n\" ]; then echo \"$n\"; break; fi; done; done",
+        names, dirs
+    );
+    let out = remote.call_remote(cmd.as_bytes())?.check(cmd.as_bytes())?;
+    Ok(out.stdout_str().lines().map(str::to_string).collect())
+}
+
+/// Size of the larger (64 bit) ELF header
+const ELF64_HEADER_SIZE: u64 = 64;
+
+/// `EF_ARM_ABI_FLOAT_SOFT` and `EF_ARM_ABI_FLOAT_HARD` of ARM `e_flags`
+const EF_ARM_ABI_FLOAT_SOFT: u32 = 0x200;
+const EF_ARM_ABI_FLOAT_HARD: u32 = 0x400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatAbi {
+    Soft,
+    Hard
+}
+
+/// What an ELF file is built for, from its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElfArch {
+    pub machine: u16,
+    pub is_64: bool,
+    pub little_endian: bool,
+    /// ARM only. `None` if the header does not tell
+    pub float: Option<FloatAbi>
+}
+
+impl Display for ElfArch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ELF{} {}", header::machine_to_str(self.machine), if self.is_64 { 64 } else { 32 }, if self.little_endian { "LE" } else { "BE" })?;
+        match self.float {
+            Some(FloatAbi::Soft) => write!(f, " soft-float"),
+            Some(FloatAbi::Hard) => write!(f, " hard-float"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ElfArch {
+    /// `None` if the file is not ELF
+    pub fn of_file(path: &Path) -> Result<Option<Self>, ElfError> {
+        let mut bytes = Vec::new();
+        std::fs::File::open(path)
+            .and_then(|f| f.take(ELF64_HEADER_SIZE).read_to_end(&mut bytes))
+            .map_err(ElfError::IOError)?;
+        if !bytes.starts_with(header::ELFMAG) {
+            return Ok(None);
+        }
+        let header = Elf::parse_header(&bytes).map_err(ElfError::ParseError)?;
+        let float = match header.e_machine {
+            EM_ARM if header.e_flags & EF_ARM_ABI_FLOAT_HARD != 0 => Some(FloatAbi::Hard),
+            EM_ARM if header.e_flags & EF_ARM_ABI_FLOAT_SOFT != 0 => Some(FloatAbi::Soft),
+            _ => None
+        };
+        Ok(Some(ElfArch {
+            machine: header.e_machine,
+            is_64: header.e_ident[header::EI_CLASS] == header::ELFCLASS64,
+            little_endian: header.e_ident[header::EI_DATA] == header::ELFDATA2LSB,
+            float
+        }))
+    }
+
+    /// Expected architecture of binaries built for `triple`. `None` for unknown architectures
+    pub fn for_triple(triple: &str) -> Option<Self> {
+        let arch = triple.split('-').next()?;
+        let env = triple.rsplit('-').next().unwrap_or_default();
+        let (machine, is_64, little_endian) = match arch {
+            "aarch64" => (EM_AARCH64, true, true),
+            "aarch64_be" => (EM_AARCH64, true, false),
+            "x86_64" => (EM_X86_64, true, true),
+            "i386" | "i586" | "i686" => (EM_386, false, true),
+            "mips" => (EM_MIPS, false, false),
+            "mipsel" => (EM_MIPS, false, true),
+            "mips64" => (EM_MIPS, true, false),
+            "mips64el" => (EM_MIPS, true, true),
+            "powerpc" => (EM_PPC, false, false),
+            "powerpc64" => (EM_PPC64, true, false),
+            "powerpc64le" => (EM_PPC64, true, true),
+            a if a.starts_with("riscv64") => (EM_RISCV, true, true),
+            a if a.starts_with("riscv32") => (EM_RISCV, false, true),
+            a if a.starts_with("armeb") || a.starts_with("thumbeb") => (EM_ARM, false, false),
+            a if a.starts_with("arm") || a.starts_with("thumb") => (EM_ARM, false, true),
+            _ => return None
+        };
+        let float = match machine {
+            EM_ARM if env.ends_with("hf") => Some(FloatAbi::Hard),
+            EM_ARM if env.ends_with("eabi") => Some(FloatAbi::Soft),
+            _ => None
+        };
+        Some(ElfArch { machine, is_64, little_endian, float })
+    }
+
+    /// Whether a binary for `self` fits `expected`. Float ABI is compared only when both know it
+    pub fn matches(&self, expected: &ElfArch) -> bool {
+        self.machine == expected.machine
+            && self.is_64 == expected.is_64
+            && self.little_endian == expected.little_endian
+            && match (self.float, expected.float) {
+                (Some(a), Some(b)) => a == b,
+                _ => true
+            }
+    }
+
+    /// Suffix of the `qemu-<arch>` user mode emulator running binaries for `self`. `None` for unknown machines
+    pub fn qemu_arch(&self) -> Option<&'static str> {
+        let arch = match (self.machine, self.is_64, self.little_endian) {
+            (EM_ARM, _, true) => "arm",
+            (EM_ARM, _, false) => "armeb",
+            (EM_AARCH64, _, true) => "aarch64",
+            (EM_AARCH64, _, false) => "aarch64_be",
+            (EM_386, _, _) => "i386",
+            (EM_X86_64, _, _) => "x86_64",
+            (EM_MIPS, false, false) => "mips",
+            (EM_MIPS, false, true) => "mipsel",
+            (EM_MIPS, true, false) => "mips64",
+            (EM_MIPS, true, true) => "mips64el",
+            (EM_PPC, _, _) => "ppc",
+            (EM_PPC64, _, false) => "ppc64",
+            (EM_PPC64, _, true) => "ppc64le",
+            (EM_RISCV, false, _) => "riscv32",
+            (EM_RISCV, true, _) => "riscv64",
+            _ => return None
+        };
+        Some(arch)
+    }
+
+    /// Whether a kernel reporting `uname_m` runs binaries for `self`. `None` for unknown machines
+    pub fn runs_on(&self, uname_m: &str) -> Option<bool> {
+        let ok = match uname_m {
+            "aarch64" | "arm64" => self.machine == EM_AARCH64 || self.machine == EM_ARM,
+            "x86_64" | "amd64" => self.machine == EM_X86_64 || self.machine == EM_386,
+            "i386" | "i486" | "i586" | "i686" => self.machine == EM_386,
+            "mips" | "mipsel" => self.machine == EM_MIPS && !self.is_64,
+            "mips64" => self.machine == EM_MIPS,
+            "ppc" => self.machine == EM_PPC,
+            "ppc64" | "ppc64le" => self.machine == EM_PPC64 || self.machine == EM_PPC,
+            "riscv64" => self.machine == EM_RISCV,
+            "riscv32" => self.machine == EM_RISCV && !self.is_64,
+            m if m.starts_with("arm") => self.machine == EM_ARM,
+            _ => return None
+        };
+        Some(ok)
+    }
+}
+
+fn arch_err(msg: String) -> DeployError {
+    DeployError::new(ErrorKind::Arch, Box::new(msg))
+}
+
+/// Refuses ELF files among `paths` which are not built for `triple` or can not run on a device reporting `uname_m`.
+/// Non ELF files are skipped
+pub fn check_arch<'a>(paths: impl Iterator<Item = &'a Path>, triple: &str, uname_m: Option<&str>) -> DeployResult<()> {
+    let expected = ElfArch::for_triple(triple);
+    for path in paths {
+        let arch = match ElfArch::of_file(path).map_err(|err| arch_err(format!("{:?}: {}", path, err)))? {
+            Some(arch) => arch,
+            None => continue
+        };
+        if let Some(expected) = expected {
+            if !arch.matches(&expected) {
+                return Err(arch_err(format!(
+                    "{:?} is {} but target {} needs {}",
+                    path, arch, triple, expected
+                )));
+            }
+        }
+        if let Some(uname_m) = uname_m {
+            if arch.runs_on(uname_m) == Some(false) {
+                return Err(arch_err(format!(
+                    "{:?} is {} but the device is {}. Was it built for the host? Set the target with `cargo condep configure --target <triple>`",
+                    path, arch, uname_m
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `uname -m` of the device
+pub fn device_machine<R: CallRemote + ?Sized>(remote: &mut R) -> DeployResult<String> {
+    let cmd = b"uname -m";
+    remote.call_remote(cmd)?.check(cmd).map(|out| out.stdout_str().trim().to_string())
+}
+
+/// Symbol version an ELF file requires from a shared library, from `.gnu.version_r`
+#[derive(Debug, Clone)]
+pub struct VersionNeed {
+    /// soname, e.g. `libc.so.6`
+    pub library: String,
+    /// e.g. `GLIBC_2.28`
+    pub version: String,
+    /// Undefined dynamic symbols bound to the version
+    pub symbols: Vec<String>
+}
+
+/// Versioned symbol requirements of the ELF file at `path`. Empty if it has no `.gnu.version_r`
+pub fn version_needs(path: &Path) -> Result<Vec<VersionNeed>, ElfError> {
+    let bytes = read(path)?;
+    let elf = Elf::parse(&bytes).map_err(ElfError::ParseError)?;
+    let verneed = match &elf.verneed {
+        Some(verneed) => verneed,
+        None => return Ok(Vec::new())
+    };
+    let str_at = |offset| elf.dynstrtab.get_at(offset).unwrap_or_default().to_string();
+
+    let mut result = Vec::new();
+    // version index used in `.gnu.version` -> index in `result`
+    let mut by_index = BTreeMap::new();
+    for need in verneed.iter() {
+        for aux in need.iter() {
+            by_index.insert(aux.vna_other, result.len());
+            result.push(VersionNeed { library: str_at(need.vn_file), version: str_at(aux.vna_name), symbols: Vec::new() });
+        }
+    }
+
+    if let Some(versym) = &elf.versym {
+        for (i, sym) in elf.dynsyms.iter().enumerate().filter(|(_, sym)| sym.st_shndx == SHN_UNDEF as usize) {
+            if let Some(&n) = versym.get_at(i).and_then(|v| by_index.get(&v.version())) {
+                result[n].symbols.push(str_at(sym.st_name));
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Versions a shared library defines in `.gnu.version_d`, without the soname entry
+pub fn version_defs(bytes: &[u8]) -> Result<BTreeSet<String>, ElfError> {
+    let elf = Elf::parse(bytes).map_err(ElfError::ParseError)?;
+    let verdef = match &elf.verdef {
+        Some(verdef) => verdef,
+        None => return Ok(BTreeSet::new())
+    };
+    Ok(verdef
+        .iter()
+        .filter(|def| def.vd_flags & VER_FLG_BASE == 0)
+        // the first aux entry names the version, the rest name its parents
+        .filter_map(|def| def.iter().next())
+        .filter_map(|aux| elf.dynstrtab.get_at(aux.vda_name))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Requirements among `needs` which `defs` (soname -> defined versions) does not satisfy.
+/// Libraries missing from `defs` are not checked
+pub fn unsatisfied<'a>(needs: &'a [VersionNeed], defs: &BTreeMap<String, BTreeSet<String>>) -> Vec<&'a VersionNeed> {
+    needs
+        .iter()
+        .filter(|need| defs.get(&need.library).is_some_and(|versions| !versions.contains(&need.version)))
+        .collect()
+}
+
+/// Content of `soname` from the first of `dirs` on the device which has it. `None` if none has
+pub fn fetch_from_device<R: CallRemote + ?Sized>(remote: &mut R, soname: &str, dirs: &[PathBuf]) -> DeployResult<Option<Vec<u8>>> {
+    let dirs = dirs.iter().map(|d| shell_quote(&d.to_string_lossy())).collect::<Vec<_>>().join(" ");
+    let cmd = format!(
+        "n={}; for d in {}; do if [ -f \"$d/$n\" ]; then cat \"$d/$n\"; exit; fi; done; exit 3",
+        shell_quote(soname), dirs
+    );
+    let out = remote.call_remote(cmd.as_bytes())?;
+    match out.status {
+        3 => Ok(None),
+        _ => out.check(cmd.as_bytes()).map(|out| Some(out.stdout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dependencies_skip_files_which_are_not_elf() {
+        let script = std::env::temp_dir().join(format!("condep-elf-script-{}.sh", std::process::id()));
+        std::fs::write(&script, "#!/bin/sh\necho hi\n").unwrap();
+
+        let deps = resolve_dependencies(std::slice::from_ref(&script), &[]).unwrap();
+        assert!(deps.found.is_empty() && deps.unresolved.is_empty());
+
+        // the test binary itself links to libc
+        let exe = std::env::current_exe().unwrap();
+        let deps = resolve_dependencies(&[script.clone(), exe], &[]).unwrap();
+        assert!(deps.unresolved.iter().any(|soname| soname.starts_with("libc.so")));
+
+        std::fs::remove_file(&script).unwrap();
+    }
+
+    fn arch(machine: u16, is_64: bool, little_endian: bool, float: Option<FloatAbi>) -> ElfArch {
+        ElfArch { machine, is_64, little_endian, float }
+    }
+
+    #[test]
+    fn triples_map_to_elf_headers() {
+        let triple = |t: &str| ElfArch::for_triple(t);
+        assert_eq!(triple("armv7-unknown-linux-gnueabihf"), Some(arch(EM_ARM, false, true, Some(FloatAbi::Hard))));
+        assert_eq!(triple("arm-unknown-linux-gnueabi"), Some(arch(EM_ARM, false, true, Some(FloatAbi::Soft))));
+        assert_eq!(triple("thumbv7neon-unknown-linux-musleabihf"), Some(arch(EM_ARM, false, true, Some(FloatAbi::Hard))));
+        assert_eq!(triple("armebv7r-none-eabi"), Some(arch(EM_ARM, false, false, Some(FloatAbi::Soft))));
+        assert_eq!(triple("aarch64-unknown-linux-gnu"), Some(arch(EM_AARCH64, true, true, None)));
+        assert_eq!(triple("x86_64-unknown-linux-gnu"), Some(arch(EM_X86_64, true, true, None)));
+        assert_eq!(triple("i686-unknown-linux-gnu"), Some(arch(EM_386, false, true, None)));
+        assert_eq!(triple("mipsel-unknown-linux-musl"), Some(arch(EM_MIPS, false, true, None)));
+        assert_eq!(triple("powerpc64le-unknown-linux-gnu"), Some(arch(EM_PPC64, true, true, None)));
+        assert_eq!(triple("riscv64gc-unknown-linux-gnu"), Some(arch(EM_RISCV, true, true, None)));
+        assert_eq!(triple("wasm32-unknown-unknown"), None);
+    }
+
+    #[test]
+    fn float_abi_is_compared_only_when_both_know_it() {
+        let hard = arch(EM_ARM, false, true, Some(FloatAbi::Hard));
+        let soft = arch(EM_ARM, false, true, Some(FloatAbi::Soft));
+        let unknown = arch(EM_ARM, false, true, None);
+        assert!(hard.matches(&hard));
+        assert!(!hard.matches(&soft));
+        assert!(unknown.matches(&soft) && hard.matches(&unknown));
+        assert!(!hard.matches(&arch(EM_ARM, false, false, Some(FloatAbi::Hard))));
+        assert!(!arch(EM_AARCH64, true, true, None).matches(&unknown));
+    }
+
+    #[test]
+    fn machines_run_on_compatible_kernels() {
+        let arm = arch(EM_ARM, false, true, None);
+        let aarch64 = arch(EM_AARCH64, true, true, None);
+        assert_eq!(arm.runs_on("armv7l"), Some(true));
+        assert_eq!(arm.runs_on("aarch64"), Some(true));
+        assert_eq!(aarch64.runs_on("armv7l"), Some(false));
+        assert_eq!(arm.runs_on("x86_64"), Some(false));
+        assert_eq!(arch(EM_386, false, true, None).runs_on("x86_64"), Some(true));
+        assert_eq!(arch(EM_MIPS, true, false, None).runs_on("mips"), Some(false));
+        assert_eq!(arm.runs_on("s390x"), None);
+    }
+
+    #[test]
+    fn qemu_emulators_follow_the_triple() {
+        let qemu = |t: &str| ElfArch::for_triple(t).and_then(|a| a.qemu_arch());
+        assert_eq!(qemu("armv7-unknown-linux-gnueabihf"), Some("arm"));
+        assert_eq!(qemu("armebv7r-none-eabi"), Some("armeb"));
+        assert_eq!(qemu("aarch64-unknown-linux-gnu"), Some("aarch64"));
+        assert_eq!(qemu("i686-unknown-linux-gnu"), Some("i386"));
+        assert_eq!(qemu("mips64el-unknown-linux-gnuabi64"), Some("mips64el"));
+        assert_eq!(qemu("powerpc-unknown-linux-gnu"), Some("ppc"));
+        assert_eq!(qemu("powerpc64le-unknown-linux-gnu"), Some("ppc64le"));
+        assert_eq!(qemu("riscv64gc-unknown-linux-gnu"), Some("riscv64"));
+        assert_eq!(arch(0, false, true, None).qemu_arch(), None);
+    }
+
+    #[test]
+    fn check_arch_refuses_binaries_for_another_target() {
+        let exe = std::env::current_exe().unwrap();
+        let host = crate::config::host_triple();
+        assert!(check_arch(std::iter::once(exe.as_path()), &host, None).is_ok());
+        let other = if host.starts_with("aarch64") { "x86_64-unknown-linux-gnu" } else { "aarch64-unknown-linux-gnu" };
+        assert!(check_arch(std::iter::once(exe.as_path()), other, None).is_err());
+        assert!(check_arch(std::iter::once(exe.as_path()), "wasm32-unknown-unknown", Some("s390x")).is_ok());
+    }
+
+    /// libc loaded into the test process
+    fn host_libc() -> PathBuf {
+        std::fs::read_to_string("/proc/self/maps")
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(5))
+            .find(|path| path.ends_with("/libc.so.6") || path.contains("/libc-"))
+            .map(PathBuf::from)
+            .unwrap()
+    }
+
+    #[test]
+    fn glibc_versions_of_the_test_binary_are_checked_against_libc() {
+        let needs = version_needs(&std::env::current_exe().unwrap()).unwrap();
+        let glibc: Vec<&VersionNeed> = needs.iter().filter(|n| n.library == "libc.so.6").collect();
+        assert!(!glibc.is_empty() && glibc.iter().all(|n| n.version.starts_with("GLIBC_")));
+        assert!(glibc.iter().any(|n| !n.symbols.is_empty()));
+
+        let libc = version_defs(&read(&host_libc()).unwrap()).unwrap();
+        assert!(libc.iter().all(|v| v != "libc.so.6"), "base version is skipped");
+        let defs = BTreeMap::from([("libc.so.6".to_string(), libc)]);
+        assert!(unsatisfied(&needs, &defs).is_empty());
+
+        let too_new = VersionNeed { library: "libc.so.6".into(), version: "GLIBC_99.0".into(), symbols: vec!["future".into()] };
+        let unknown_lib = VersionNeed { library: "libother.so".into(), version: "OTHER_1.0".into(), symbols: vec![] };
+        let needs = [too_new, unknown_lib];
+        let missing: Vec<&str> = unsatisfied(&needs, &defs).iter().map(|n| n.version.as_str()).collect();
+        assert_eq!(missing, ["GLIBC_99.0"]);
+    }
+
+    #[test]
+    fn libraries_are_fetched_from_the_first_device_dir_which_has_them() {
+        use crate::local_deploy::{LocalDeploy, LocalDeployConfig};
+
+        let root = std::env::temp_dir().join(format!("condep-elf-device-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("usr/lib")).unwrap();
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(root.join("usr/lib/libc.so.6"), "first").unwrap();
+        std::fs::write(root.join("lib/libc.so.6"), "second").unwrap();
+
+        let mut depl = LocalDeploy::new(LocalDeployConfig { root: root.clone(), commands: Default::default(), hooks: Default::default() }).unwrap();
+        let dirs: Vec<PathBuf> = ["/opt/lib", "/usr/lib", "/lib"].iter().map(|d| depl.remote_path(Path::new(d))).collect();
+        assert_eq!(fetch_from_device(&mut depl, "libc.so.6", &dirs).unwrap().as_deref(), Some(b"first".as_slice()));
+        assert_eq!(fetch_from_device(&mut depl, "libm.so.6", &dirs).unwrap(), None);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
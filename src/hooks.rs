@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{deploy::{shell_quote, CallRemote, DeployResult, DeployPaths, FileKind, PlannedCommand}, config::print};
+
+/// What to do when a hook command fails
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    #[default]
+    Abort,
+    Warn,
+    Ignore
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RemoteHook {
+    /// `{path}` (shell-quoted) and `{kind}` are replaced with the deployed file and its kind in `post_file` hooks
+    pub cmd: String,
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+    /// `post_file` only: kinds of files the hook runs for, all if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kinds: Vec<FileKind>
+}
+
+impl RemoteHook {
+    pub fn new(cmd: &str, on_failure: FailurePolicy) -> Self {
+        RemoteHook { cmd: cmd.into(), on_failure, kinds: vec![] }
+    }
+
+    pub fn for_kinds(cmd: &str, on_failure: FailurePolicy, kinds: Vec<FileKind>) -> Self {
+        RemoteHook { cmd: cmd.into(), on_failure, kinds }
+    }
+
+    pub fn applies_to(&self, kind: FileKind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(&kind)
+    }
+
+    pub fn expand(&self, path: &Path, kind: FileKind) -> String {
+        self.cmd
+            .replace("{path}", &shell_quote(&path.to_string_lossy()))
+            .replace("{kind}", &kind.to_string())
+    }
+
+    fn call<R: CallRemote + ?Sized>(&self, cmd: String, stage: &str, remote: &mut R) -> DeployResult<()> {
+        match remote.call_remote(cmd.as_bytes()).and_then(|out| out.check(cmd.as_bytes())) {
+            Ok(out) => { eprint!("{}", out.stdout_str()); Ok(()) },
+            Err(err) => match self.on_failure {
+                FailurePolicy::Abort => Err(err.context(format!("{} hook", stage))),
+                FailurePolicy::Warn => { print::warning(print::HOOK_FAILED, format!("{} `{}`: {}", stage, cmd, err)); Ok(()) },
+                FailurePolicy::Ignore => Ok(()),
+            }
+        }
+    }
+}
+
+/// Remote commands run around deploy, in order
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DeployHooks {
+    #[serde(default)]
+    pub pre_deploy: Vec<RemoteHook>,
+    /// Run for every deployed file
+    #[serde(default)]
+    pub post_file: Vec<RemoteHook>,
+    #[serde(default)]
+    pub post_deploy: Vec<RemoteHook>,
+    /// Run by `cargo condep uninstall` after deployed files are removed
+    #[serde(default)]
+    pub cleanup: Vec<RemoteHook>
+}
+
+impl DeployHooks {
+    pub fn run_pre_deploy<R: CallRemote + ?Sized>(&self, remote: &mut R) -> DeployResult<()> {
+        for hook in &self.pre_deploy {
+            hook.call(hook.cmd.clone(), "pre_deploy", remote)?
+        }
+        Ok(())
+    }
+
+    /// `dst` are destination paths returned by `Deploy::deploy`
+    pub fn run_post_file<R: CallRemote + ?Sized>(&self, dst: &DeployPaths, remote: &mut R) -> DeployResult<()> {
+        for (kind, path) in dst.iter() {
+            for hook in self.post_file.iter().filter(|h| h.applies_to(kind)) {
+                hook.call(hook.expand(&remote.remote_path(path), kind), "post_file", remote)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Commands in the order they would run for files deployed to `dst`, paths mapped by `remote_path` as in `run_post_file`
+    pub fn plan(&self, dst: &DeployPaths, remote_path: impl Fn(&Path) -> PathBuf) -> Vec<PlannedCommand> {
+        let pre = self.pre_deploy.iter().map(|h| PlannedCommand { stage: "pre_deploy", cmd: h.cmd.clone() });
+        let post_file = dst.iter().flat_map(|(kind, path)| {
+            let path = remote_path(path);
+            self.post_file
+                .iter()
+                .filter(move |h| h.applies_to(kind))
+                .map(move |h| PlannedCommand { stage: "post_file", cmd: h.expand(&path, kind) })
+        });
+        let post = self.post_deploy.iter().map(|h| PlannedCommand { stage: "post_deploy", cmd: h.cmd.clone() });
+        pre.chain(post_file).chain(post).collect()
+    }
+
+    pub fn run_post_deploy<R: CallRemote + ?Sized>(&self, remote: &mut R) -> DeployResult<()> {
+        for hook in &self.post_deploy {
+            hook.call(hook.cmd.clone(), "post_deploy", remote)?
+        }
+        Ok(())
+    }
+
+    pub fn run_cleanup<R: CallRemote + ?Sized>(&self, remote: &mut R) -> DeployResult<()> {
+        for hook in &self.cleanup {
+            hook.call(hook.cmd.clone(), "cleanup", remote)?
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_is_substituted_quoted() {
+        let hook = RemoteHook::new("sync {path} # {kind}", FailurePolicy::Abort);
+        assert_eq!(
+            hook.expand(Path::new("/opt/my app/it's \"here\"; rm -rf"), FileKind::Exec),
+            "sync '/opt/my app/it'\\''s \"here\"; rm -rf' # exec"
+        );
+    }
+
+    #[test]
+    fn plan_shows_the_quoted_commands() {
+        let hooks = DeployHooks {
+            pre_deploy: vec![RemoteHook::new("mount -o rw,remount /ebrmain", FailurePolicy::Abort)],
+            post_file: vec![RemoteHook::for_kinds("sync {path}", FailurePolicy::Warn, vec![FileKind::Lib])],
+            ..Default::default()
+        };
+        let dst = DeployPaths { execs: vec!["/bin/a b".into()], libs: vec!["/lib/lib x.so".into()], ..Default::default() };
+        let cmds: Vec<String> = hooks.plan(&dst, Path::to_path_buf).into_iter().map(|c| c.cmd).collect();
+        assert_eq!(cmds, ["mount -o rw,remount /ebrmain", "sync '/lib/lib x.so'"]);
+    }
+}
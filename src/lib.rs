@@ -1,5 +1,16 @@
 
 
+pub mod backup;
+pub mod checksum;
 pub mod config;
+pub mod debug;
 pub mod deploy;
+pub mod elf;
+pub mod hooks;
+pub mod manifest;
+pub mod metadata;
+pub mod project;
+pub mod runner;
+pub mod strip;
 pub mod ssh_deploy;
+pub mod local_deploy;
@@ -0,0 +1,156 @@
+use std::{path::{Path, PathBuf}, process::{Command, Stdio}, os::unix::fs::PermissionsExt};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{deploy::{Deploy, DeployResult, DeployError, CallRemote, PlanEntry, RemoteOutput}, hooks::DeployHooks};
+
+/// How commands are run against the staging root
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LocalCommandMode {
+    /// `sh -c` with the root as working directory. Device paths are passed relative to the root
+    #[default]
+    WorkDir,
+    /// `chroot <root> sh -c` (needs privileges and a shell inside the root)
+    Chroot
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LocalDeployConfig {
+    pub root: PathBuf,
+    #[serde(default)]
+    pub commands: LocalCommandMode,
+    /// Run instead of the device hooks, which would act on the host. None by default
+    #[serde(flatten)]
+    pub hooks: DeployHooks
+}
+
+impl LocalDeployConfig {
+    /// Location of device path `path` inside the root
+    pub fn staged_path(&self, path: &Path) -> PathBuf {
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// How device path `path` is addressed in commands
+    pub fn command_path(&self, path: &Path) -> PathBuf {
+        match self.commands {
+            LocalCommandMode::WorkDir => path.strip_prefix("/").unwrap_or(path).to_path_buf(),
+            LocalCommandMode::Chroot => path.to_path_buf(),
+        }
+    }
+}
+
+/// Deploys into a local directory (staging sysroot) as if it was the device root
+pub struct LocalDeploy {
+    config: LocalDeployConfig
+}
+
+impl LocalDeploy {
+    /// Creates the root if it does not exist
+    pub fn new(config: LocalDeployConfig) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&config.root).map(|()| LocalDeploy { config })
+    }
+
+    fn command(&self, cmd: &[u8]) -> Command {
+        let cmd = String::from_utf8_lossy(cmd).into_owned();
+        match self.config.commands {
+            LocalCommandMode::WorkDir => {
+                let mut c = Command::new("/bin/sh");
+                c.arg("-c").arg(cmd).current_dir(&self.config.root);
+                c
+            },
+            LocalCommandMode::Chroot => {
+                let mut c = Command::new("chroot");
+                c.arg(&self.config.root).arg("/bin/sh").arg("-c").arg(cmd);
+                c
+            },
+        }
+    }
+}
+
+fn remove_existing(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(())
+    }
+}
+
+impl Deploy for LocalDeploy {
+    fn deploy(&mut self, files: &[PlanEntry]) -> DeployResult<()> {
+        for entry in files {
+            let staged = self.config.staged_path(&entry.dst);
+
+            eprintln!("Coping: {} -> {}", entry.src.to_str().unwrap(), staged.to_str().unwrap());
+
+            std::fs::create_dir_all(staged.parent().unwrap())
+                .and_then(|()| match &entry.link {
+                    Some(target) => remove_existing(&staged)
+                        .and_then(|()| std::os::unix::fs::symlink(target, &staged)),
+                    None => std::fs::copy(&entry.src, &staged)
+                        .and_then(|_| std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(entry.mode))),
+                })
+                .map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
+        }
+        Ok(())
+    }
+}
+
+impl CallRemote for LocalDeploy {
+    fn call_remote(&mut self, cmd: &[u8]) -> DeployResult<RemoteOutput> {
+        eprintln!("running cmd: {:?}", String::from_utf8_lossy(cmd));
+        self.command(cmd)
+            .output()
+            .map(|out| RemoteOutput { status: out.status.code().unwrap_or(-1), stdout: out.stdout, stderr: out.stderr })
+            .map_err(|err| DeployError::new_remote_call_err(Box::new(err)))
+    }
+
+    fn call_remote_streaming(&mut self, cmd: &[u8]) -> DeployResult<i32> {
+        self.command(cmd)
+            .stdin(Stdio::null())
+            .status()
+            .map(|status| status.code().unwrap_or(-1))
+            .map_err(|err| DeployError::new_remote_call_err(Box::new(err)))
+    }
+
+    fn remote_path(&self, path: &Path) -> PathBuf {
+        self.config.command_path(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deploy::FileKind;
+
+    #[test]
+    fn deploys_into_the_root_and_runs_commands_there() {
+        let dir = std::env::temp_dir().join(format!("condep-local-{}", std::process::id()));
+        let src = dir.join("tool");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&src, "#!/bin/sh\n").unwrap();
+
+        let config: LocalDeployConfig = serde_yaml::from_str(&format!("root: {}\npost_file:\n  - cmd: test -x {{path}}\n", dir.join("root").display())).unwrap();
+        assert_eq!(config.hooks.post_file.len(), 1);
+        let mut depl = LocalDeploy::new(config).unwrap();
+        let entry = |dst: &str, link: Option<&str>| PlanEntry {
+            kind: FileKind::Exec, src: src.clone(), dst: dst.into(), size: 10, mode: 0o750,
+            owner: None, group: None, link: link.map(PathBuf::from), overwrites: None
+        };
+        depl.deploy(&[entry("/opt/bin/tool", None), entry("/usr/bin/tool", Some("/opt/bin/tool"))]).unwrap();
+
+        let staged = dir.join("root/opt/bin/tool");
+        assert_eq!(std::fs::read_to_string(&staged).unwrap(), "#!/bin/sh\n");
+        assert_eq!(std::fs::metadata(&staged).unwrap().permissions().mode() & 0o777, 0o750);
+        assert_eq!(std::fs::read_link(dir.join("root/usr/bin/tool")).unwrap(), Path::new("/opt/bin/tool"));
+
+        assert_eq!(depl.remote_path(Path::new("/opt/bin/tool")), Path::new("opt/bin/tool"));
+        let out = depl.call_remote(b"cat opt/bin/tool; echo err >&2; exit 3").unwrap();
+        assert_eq!((out.status, out.stdout.as_slice(), out.stderr.as_slice()), (3, b"#!/bin/sh\n".as_slice(), b"err\n".as_slice()));
+
+        let dst = crate::deploy::DeployPaths { execs: vec!["/opt/bin/tool".into()], ..Default::default() };
+        let hooks = depl.config.hooks.clone();
+        hooks.run_post_file(&dst, &mut depl).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 
-use std::{collections::BTreeMap, path::{PathBuf, Path}, string::FromUtf8Error};
-use cargo_condep::{config::{BuildMultitargetConfig, BuildConfiguration, ValueAlternatives, LinkSource, EnvStr, LinkSourceType, LogLevel, VarAction, self, EnvPair}, deploy::{DeployConfig, self, Noop, DeployPaths}, ssh_deploy::{SSHDeploy, SSHUserAndHost}};
+use std::{collections::{BTreeMap, BTreeSet}, path::{PathBuf, Path}, string::FromUtf8Error};
+use cargo_condep::{debug::{self, GdbSession}, config::{BuildMultitargetConfig, RunnerMode, BuildConfiguration, ValueAlternatives, LinkSource, EnvStr, LinkSourceType, LogLevel, VarAction, self, EnvPair}, deploy::{DeployConfig, self, Noop, DeployPaths, FileKind, DeployResult, DeployPlan, DeployReport, PlanEntry, DeployError, StripMode}, checksum, backup::{self, BackupRecord}, manifest::{self, DeployManifest, FileStatus}, elf::{self, VersionNeed}, strip::StripTools, ssh_deploy::{SSHDeploy, SSHUserAndHost}, local_deploy::{LocalDeploy, LocalDeployConfig}, project::{self, ProjectConfig, ProjectConfigError, ProjectLayout}, runner::{self, RemoteRunConfig}, metadata::{self, ArtifactSelection}, hooks::{DeployHooks, RemoteHook, FailurePolicy}};
 
 
 
@@ -11,7 +11,15 @@ use termion::color::{Fg, Reset, LightYellow, LightMagenta};
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 struct SSHDeployConfig {
     pub paths: DeployConfig,
-    pub ssh: SSHUserAndHost
+    pub ssh: SSHUserAndHost,
+    /// Staging directory used by `--method local`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local: Option<LocalDeployConfig>,
+    /// Used by `cargo condep run --remote`
+    #[serde(default)]
+    pub run: RemoteRunConfig,
+    #[serde(flatten)]
+    pub hooks: DeployHooks
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
@@ -21,14 +29,17 @@ struct WholeConfig {
 }
 
 struct ConfigProvider {
-    pub cache_path: PathBuf
+    pub cache_path: PathBuf,
+    /// directory to start project config discovery from
+    pub project_dir: Option<PathBuf>
 }
 
 #[derive(Debug)]
 enum ConfigReadError {
     IOError(std::io::Error),
     FromUtf8Error(FromUtf8Error),
-    YamlError(serde_yaml::Error)
+    YamlError(serde_yaml::Error),
+    ProjectError(ProjectConfigError)
 }
 
 #[derive(Debug)]
@@ -43,12 +54,17 @@ impl Default for ConfigProvider {
             cache_path: dirs::home_dir()
                 .unwrap()
                 .join(".cargo")
-                .join("condep") 
+                .join("condep"),
+            project_dir: None
         } 
     }
 }
 
 impl ConfigProvider {
+    pub fn for_project(project_dir: PathBuf) -> Self {
+        ConfigProvider { project_dir: Some(project_dir), ..Default::default() }
+    }
+
     pub fn install_from_bytes(&self, bytes: &[u8]) -> std::io::Result<()> {
         std::fs::create_dir_all(self.cache_path.as_path())
             .and_then(|()| std::fs::write(self.cache_path.join("config.yaml"), bytes))
@@ -67,7 +83,12 @@ impl ConfigProvider {
             )
     }
 
-    pub fn read(&self) -> Result<WholeConfig, ConfigReadError> {
+    /// Local state of deploys to one device (backups, manifests)
+    pub fn device_dir(&self, device_id: &str) -> PathBuf {
+        self.cache_path.join("devices").join(device_id)
+    }
+
+    fn read_global(&self) -> Result<serde_yaml::Value, ConfigReadError> {
         std::fs::read(self.cache_path.join("config.yaml"))
             .map_err(ConfigReadError::IOError)
             .and_then(|bytes| String::from_utf8(bytes)
@@ -77,6 +98,35 @@ impl ConfigProvider {
                 )
             )
     }
+
+    fn read_project(&self) -> Result<Option<ProjectConfig>, ConfigReadError> {
+        match &self.project_dir {
+            Some(dir) => ProjectConfig::discover(dir).map_err(ConfigReadError::ProjectError),
+            None => Ok(None),
+        }
+    }
+
+    /// Global config is a lower-priority layer overridden field by field by the project config
+    pub fn read(&self) -> Result<WholeConfig, ConfigReadError> {
+        let project = self.read_project()?;
+        let value = match (self.read_global(), project) {
+            (Ok(mut global), Some(project)) => { project::merge_yaml(&mut global, project.value); global },
+            (Ok(global), None) => global,
+            (Err(ConfigReadError::IOError(_)), Some(project)) => project.value,
+            (Err(err), _) => return Err(err),
+        };
+        serde_yaml::from_value(value).map_err(ConfigReadError::YamlError)
+    }
+
+    /// `read` which exits with a message telling how to fix the config
+    pub fn read_or_exit(&self) -> WholeConfig {
+        match self.read() {
+            Ok(config) => config,
+            Err(ConfigReadError::IOError(_)) => config::print::fatal(config::print::BAD_CONFIG, "config not installed: use `cargo condep install`".into()),
+            Err(ConfigReadError::ProjectError(err)) => config::print::fatal(config::print::BAD_CONFIG, format!("project config is broken: {:?}", err)),
+            Err(err) => config::print::fatal(config::print::BAD_CONFIG, format!("config installed but broken: use `cargo condep install` to reinstall it ({:?})", err)),
+        }
+    }
 }
 
 
@@ -97,7 +147,7 @@ fn pb_default_config() -> BuildMultitargetConfig {
         vec![LinkSource::new(LinkSourceType::Env, String::from("PB_SYSTEM_PATH"))],
         Some("$PB_SDK_DIR/usr/bin/arm-obreey-linux-gnueabi-g++".into()),
         vec!["$TOOLCHAIN_PATH/$TOOLCHAIN_PREFIX/sysroot/usr/local/lib".into()]
-        )),
+        ).with_sysroot("$TOOLCHAIN_PATH/$TOOLCHAIN_PREFIX/sysroot".into())),
     ]),
 	BuildConfiguration::new(
         vec![
@@ -118,6 +168,7 @@ fn pb_default_deploy_ssh_user_host() -> SSHUserAndHost {
     SSHUserAndHost {
         user: "root".into(),
         host: "192.168.205.1".into(),
+        ..Default::default()
     }
 }
 
@@ -126,7 +177,22 @@ fn pb_default_deploy_config() -> DeployConfig {
         execs_path: PathBuf::from("/ebrmain/bin"),
         libs_path: PathBuf::from("/ebrmain/lib"),
         config_path: PathBuf::from("/ebrmain/config"),
-        user_path: PathBuf::from("/mnt/ext1/system")
+        user_path: PathBuf::from("/mnt/ext1/system"),
+        manifest_path: None,
+        files: Default::default(),
+        device_lib_paths: vec![PathBuf::from("/lib"), PathBuf::from("/usr/lib")],
+        permissions: Default::default(),
+        overrides: BTreeMap::new(),
+        strip: StripMode::None
+    }
+}
+
+fn pb_default_deploy_hooks() -> DeployHooks {
+    DeployHooks {
+        pre_deploy: vec![RemoteHook::new("mount -o rw,remount /ebrmain", FailurePolicy::Abort)],
+        post_file: vec![],
+        post_deploy: vec![],
+        cleanup: vec![]
     }
 }
 
@@ -134,8 +200,9 @@ fn pb_whole_config() -> WholeConfig {
     let c = pb_default_config();
     let d = pb_default_deploy_config();
     let ssh = pb_default_deploy_ssh_user_host();
+    let hooks = pb_default_deploy_hooks();
     
-    WholeConfig { config: c, deploy: SSHDeployConfig{ paths: d, ssh: ssh } }
+    WholeConfig { config: c, deploy: SSHDeployConfig{ paths: d, ssh: ssh, local: None, run: Default::default(), hooks } }
 }
 
 
@@ -170,8 +237,16 @@ struct SomeAction {
 #[derive(clap::Subcommand)]
 enum CondepSubCommand {
     Configure(Configure),
+    Build(Build),
+    /// Forwards arguments after the executable as is, so it works as a cargo target runner
+    #[clap(trailing_var_arg = true)]
     Run(Run),
     Deploy(Deploy),
+    Rollback(Rollback),
+    Uninstall(Uninstall),
+    Status(Status),
+    Check(Check),
+    Debug(Debug),
     Install(Install)
 }
 
@@ -183,59 +258,111 @@ struct Configure {
 
     #[clap(long, parse(from_str), default_value = "pretty")]
     log_level: LogLevel,
+
+    /// How `cargo run` and `cargo test` execute binaries: host, remote or qemu.
+    /// Defaults to remote for cross targets and host otherwise
+    #[clap(long, parse(try_from_str))]
+    runner: Option<RunnerMode>,
 }
 
 impl Configure {
-    fn exec(self) {
+    fn exec(self, config_provider: ConfigProvider, layout: &ProjectLayout) {
         let alias  = [("deploy".into(), "condep deploy".into())].into();
-        match ConfigProvider::default().read() {
-            Ok(config) => {
-                match config.config.to_config_toml(&self.target, self.log_level, alias) {
-                    Some(tml) => {
-                        std::fs::create_dir_all(".cargo").unwrap();
-                        std::fs::write(".cargo/config.toml", toml::to_string_pretty(&tml).unwrap()).unwrap();
-                    },
-                    None => println!("undefined target"),
+        let config = config_provider.read_or_exit();
+        match config.config.to_config_toml(&self.target, self.log_level, alias, self.runner, &layout.package_root) {
+            Some(tml) => {
+                let config_path = layout.cargo_config_path();
+                std::fs::create_dir_all(config_path.parent().unwrap()).unwrap();
+                let existing = match std::fs::read_to_string(&config_path) {
+                    Ok(existing) => existing,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+                    Err(err) => config::print::fatal(config::print::BAD_CONFIG, format!("{:?}: {}", config_path, err)),
+                };
+                match tml.merge_into_str(existing.as_str()) {
+                    Ok(merged) => std::fs::write(config_path, merged).unwrap(),
+                    Err(err) => config::print::fatal(config::print::BAD_CONFIG, format!("{:?}: {}", config_path, err)),
                 }
             },
-            Err(err) => match err {
-                ConfigReadError::IOError(_) => println!("Config not installed: use `cargo condep install`"),
-                _ => println!("Config installed but broken: use `cargo condep install` to reinstall it"),
-            },
+            None => println!("undefined target"),
         }
     }
 }
 
 #[derive(clap::Args, Debug)]
 struct RunDelegate {
-    pub exe: String,
-    pub args: Vec<String>
+    /// Executable followed by its arguments
+    #[clap(required = true, multiple_values = true, allow_hyphen_values = true, value_name = "EXE")]
+    pub command: Vec<String>
+}
+
+impl RunDelegate {
+    fn exe(&self) -> &str { &self.command[0] }
+
+    fn args(&self) -> &[String] { &self.command[1..] }
 }
 
 #[derive(clap::Args)]
-#[clap(author, version, about, long_about = Some("Run with configured LD_LIBRARY_PATH"))]
+#[clap(author, version, about, long_about = Some("Run with the configured environment"))]
 struct Run {
+    /// Upload the executable to the device and run it there
+    #[clap(long)]
+    remote: bool,
+    #[clap(long, parse(try_from_str), default_value = "ssh")]
+    method: DeployMethod,
+    /// Run the executable under qemu user mode emulation
+    #[clap(long)]
+    qemu: bool,
+    /// Target root qemu loads shared libraries from
+    #[clap(long, parse(from_os_str))]
+    sysroot: Option<PathBuf>,
     #[clap(flatten)]
     delegate: RunDelegate,
 }
 
 impl Run {
-    fn exec(self) {
+    fn exec(self, config_provider: ConfigProvider, layout: &ProjectLayout) {
 
         let cwd = std::env::current_dir().unwrap();
-        let config_toml: config::toml::Config = toml::from_slice(std::fs::read(cwd.join(".cargo/config.toml")).unwrap().as_slice()).unwrap();
+        if self.remote {
+            let config = config_provider.read_or_exit().deploy;
+            let env = config.run.forwarded_env(std::env::vars());
+            let mut depl = self.method.depl(&config);
+            match config.run.run(depl.as_mut(), &cwd.join(self.delegate.exe()), self.delegate.args(), &env) {
+                Ok(status) => std::process::exit(status),
+                Err(err) => config::print::fatal(config::print::DEPLOY_FAILED, format!("{}", err)),
+            }
+        }
+        let config_toml = read_cargo_config(layout);
+        let config_root = layout.cargo_config_path().parent().and_then(Path::parent).map(Path::to_path_buf).unwrap_or_default();
+        for (key, value) in config_toml.process_env(&config_root) {
+            eprintln!("setting {} = {}", key, value);
+            std::env::set_var(key, value);
+        }
+        // runner-only env needs the installed config, plain `[env]` does not
+        if let Some(build) = config_provider.read().ok().as_ref().and_then(|config| config.config.get(&config_toml.build.target)) {
+            for (key, value) in build.apply_run_env(&|s: &String| Path::new(s).exists()) {
+                match value {
+                    Some(value) => eprintln!("setting {} = {}", key, value),
+                    None => eprintln!("not setting {}: none of its alternatives exists", key),
+                }
+            }
+        }
 
-        let ldlp_key = "LD_LIBRARY_PATH";
-        config_toml.env.get(ldlp_key).map(|ldlp| {
-            println!("setting {} = {}", ldlp_key, ldlp);
-            std::env::set_var(ldlp_key, ldlp);
-        });
-        println!("running: {:?} {:?}", cwd.join(&self.delegate.exe), self.delegate.args);
+        if self.qemu {
+            let ldlp = std::env::var("LD_LIBRARY_PATH").ok();
+            let status = runner::qemu_command(&cwd.join(self.delegate.exe()), self.delegate.args(), self.sysroot.as_deref(), ldlp.as_deref())
+                .and_then(|mut cmd| cmd.status().map_err(|err| format!("can not run {:?}: {}", cmd.get_program(), err)));
+            match status {
+                Ok(status) => std::process::exit(status.code().unwrap_or(-1)),
+                Err(err) => config::print::fatal(config::print::BAD_STATUS, err),
+            }
+        }
+        eprintln!("running: {:?} {:?}", cwd.join(self.delegate.exe()), self.delegate.args());
 
-        if !self.delegate.exe.is_empty() {  
+        if !self.delegate.exe().is_empty() {  
 
-            let mut child = std::process::Command::new(&cwd.join(self.delegate.exe))
-                .args(self.delegate.args)
+            let mut child = std::process::Command::new(&cwd.join(self.delegate.exe()))
+                .args(self.delegate.args())
                 .spawn()
                 .unwrap();
 
@@ -254,6 +381,7 @@ impl Run {
 
 enum DeployMethod {
     SSH,
+    Local,
     No
 }
 
@@ -265,87 +393,744 @@ where
 
 
 impl DeployMethod {
-    fn depl(&self, user_host: &SSHUserAndHost) -> Box<dyn DeployAndCallRemote> {
+    fn describe(&self, config: &SSHDeployConfig) -> String {
         match self {
-            DeployMethod::SSH => Box::new(SSHDeploy::connect(user_host).unwrap()),
+            DeployMethod::SSH => match config.ssh.port {
+                Some(port) => format!("{}@{}:{}", config.ssh.user, config.ssh.host, port),
+                None => format!("{}@{}", config.ssh.user, config.ssh.host),
+            },
+            DeployMethod::Local => match &config.local {
+                Some(local) => format!("local {}", local.root.display()),
+                None => "local (not configured)".into(),
+            },
+            DeployMethod::No => "nothing".into(),
+        }
+    }
+
+    /// `CallRemote::remote_path` of `depl(config)` without connecting
+    fn remote_path(&self, config: &SSHDeployConfig, path: &Path) -> PathBuf {
+        match (self, &config.local) {
+            (DeployMethod::Local, Some(local)) => local.command_path(path),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// Hooks run by this method. The local method runs its own ones since device commands would act on the host
+    fn hooks<'a>(&self, config: &'a SSHDeployConfig) -> &'a DeployHooks {
+        match (self, &config.local) {
+            (DeployMethod::Local, Some(local)) => &local.hooks,
+            _ => &config.hooks,
+        }
+    }
+
+    /// `describe` usable as a directory name
+    fn device_id(&self, config: &SSHDeployConfig) -> String {
+        self.describe(config)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '@' { c } else { '_' })
+            .collect()
+    }
+
+    fn depl(&self, config: &SSHDeployConfig) -> Box<dyn DeployAndCallRemote> {
+        match self {
+            DeployMethod::SSH => match SSHDeploy::connect(&config.ssh) {
+                Ok(depl) => Box::new(depl),
+                Err(err) => config::print::fatal(config::print::DEPLOY_FAILED, format!("{}", err)),
+            },
+            DeployMethod::Local => match &config.local {
+                Some(local) => match LocalDeploy::new(local.clone()) {
+                    Ok(depl) => Box::new(depl),
+                    Err(err) => config::print::fatal(config::print::DEPLOY_FAILED, format!("{:?}: {}", local.root, err)),
+                },
+                None => config::print::fatal(config::print::DEPLOY_FAILED, "`deploy.local.root` is not configured".into()),
+            },
             DeployMethod::No => Box::new(Noop{}),
         }
     }
 }
 
-impl From<&str> for DeployMethod {
+enum PlanFormat {
+    Text,
+    Json
+}
+
+impl From<&str> for PlanFormat {
     fn from(s: &str) -> Self {
         match s {
-            "ssh" => Self::SSH,
-            _ => Self::No
+            "json" => Self::Json,
+            _ => Self::Text
         }
     }
 }
 
+impl std::str::FromStr for DeployMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ssh" => Ok(Self::SSH),
+            "local" => Ok(Self::Local),
+            "no" | "none" => Ok(Self::No),
+            _ => Err(format!("unknown deploy method {:?}, expected ssh, local or none", s))
+        }
+    }
+}
+
+/// Artifacts to pick, like in `cargo build`. Nothing selected means all bins and dynamic libs
+#[derive(clap::Args)]
+struct ArtifactArgs {
+    /// Deploy only the specified binary
+    #[clap(long = "bin")]
+    bins: Vec<String>,
+    /// Deploy only the specified example
+    #[clap(long = "example")]
+    examples: Vec<String>,
+    /// Deploy the package's cdylib/dylib
+    #[clap(long)]
+    lib: bool,
+}
+
+impl From<ArtifactArgs> for ArtifactSelection {
+    fn from(args: ArtifactArgs) -> Self {
+        ArtifactSelection { bins: args.bins, examples: args.examples, lib: args.lib }
+    }
+}
+
+/// `.cargo/config.toml` of the workspace, empty if not configured yet
+fn read_cargo_config(layout: &ProjectLayout) -> config::toml::Config {
+    let path = layout.cargo_config_path();
+    match std::fs::read_to_string(&path) {
+        Ok(content) => match config::toml::Config::parse(&content) {
+            Ok(config) => config,
+            Err(err) => config::print::fatal(config::print::BAD_CONFIG, format!("{:?}: {}", path, err)),
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => config::toml::Config::default(),
+        Err(err) => config::print::fatal(config::print::BAD_CONFIG, format!("{:?}: {}", path, err)),
+    }
+}
+
+/// Artifacts of the selected packages built for the configured target. Release is preferred if `profile` is not specified
+fn built_artifacts(layout: &ProjectLayout, config_toml: &config::toml::Config, artifacts: ArtifactArgs, profile: &Option<String>) -> DeployPaths {
+    let metadata = metadata::metadata(Some(&layout.manifest_path)).unwrap();
+    let packages = metadata.selected_packages(&layout.manifest_path);
+    let selection = ArtifactSelection::from(artifacts);
+
+    let current_target_dir = match &config_toml.build.target {
+        Some(tgt) => layout.target_dir.join(tgt),
+        None => layout.target_dir.clone(),
+    };
+
+    let all_exist = |paths: &DeployPaths| paths.execs.iter().chain(paths.libs.iter()).all(|p| p.exists());
+
+    let src = match profile {
+        Some(profile) => metadata::artifacts(&packages, &selection, &current_target_dir.join(metadata::profile_dir(profile))),
+        None => {
+            let release = metadata::artifacts(&packages, &selection, &current_target_dir.join("release"));
+            if all_exist(&release) {
+                release
+            } else {
+                metadata::artifacts(&packages, &selection, &current_target_dir.join("debug"))
+            }
+        }
+    };
+
+    if src.execs.is_empty() && src.libs.is_empty() {
+        config::print::fatal(config::print::NOTHING_TO_DEPLOY, format!("no artifacts match the selection in {:?}", layout.manifest_path))
+    }
+    if !all_exist(&src) {
+        config::print::fatal(config::print::NOTHING_TO_DEPLOY, format!("{:#?} not built. may do `cargo build`", src))
+    }
+    src
+}
+
 #[derive(clap::Args)]
 #[clap(author, version, about, long_about = Some("Deploy current turget"))]
 struct Deploy {
     
     #[clap(long, parse(from_str), default_value = "pretty")]
     log_level: LogLevel,
-    #[clap(long, parse(from_str), default_value = "ssh")]
-    method: DeployMethod
+    #[clap(long, parse(try_from_str), default_value = "ssh")]
+    method: DeployMethod,
+    /// Profile the artifacts were built with. If not specified release is used when built, otherwise debug
+    #[clap(long)]
+    profile: Option<String>,
+    #[clap(flatten)]
+    artifacts: ArtifactArgs,
+    /// Print what would be deployed without connecting to the device
+    #[clap(long)]
+    dry_run: bool,
+    /// Dry run output format: text or json
+    #[clap(long, parse(from_str), default_value = "text")]
+    format: PlanFormat,
+    /// Upload every file even if the device already has an identical copy
+    #[clap(long)]
+    force: bool,
+    /// Also deploy shared libraries the artifacts need which are missing on the device
+    #[clap(long)]
+    with_deps: bool,
+    /// Deploy binaries even if they are built for another architecture than the target or the device
+    #[clap(long)]
+    no_arch_check: bool
 }
 
 impl Deploy {
-    fn exec(self) {
+    fn exec(self, config_provider: ConfigProvider, layout: &ProjectLayout) {
+
+        let config = config_provider.read_or_exit();
+        let config_toml = read_cargo_config(layout);
+        let src = built_artifacts(layout, &config_toml, self.artifacts, &self.profile);
+
+        let options = DeployOptions {
+            dry_run: if self.dry_run { Some(self.format) } else { None },
+            force: self.force,
+            lib_search_paths: if self.with_deps {
+                // resolved like in `build` so paths may use variables set by `sources`
+                config.config
+                    .resolve(&config_toml.build.target, self.log_level, &layout.package_root)
+                    .map(|resolved| resolved.lib_search_paths)
+            } else {
+                None
+            },
+            arch_check: if self.no_arch_check { None } else { Some(config_toml.build.target.clone().unwrap_or_else(config::host_triple)) },
+            strip_tools: StripTools::new(&config_toml.env_values(), config_toml.build.target.as_deref().and_then(|t| config_toml.target_val(t, config::toml::Config::LINKER)))
+        };
+        deploy_artifacts(&config_provider, &config.deploy, &self.method, src, options)
+    }
+}
 
-        match ConfigProvider::default().read() {
-            Ok(config) => {
-                let mut depl = self.method.depl(&config.deploy.ssh);
-        
-                let cwd = std::env::current_dir().unwrap();
-        
-                let config_toml: config::toml::Config = toml::from_slice(std::fs::read(cwd.join(".cargo/config.toml")).unwrap().as_slice()).unwrap();
-                let cargo_toml: config::toml::Cargo = toml::from_slice(std::fs::read(cwd.join("Cargo.toml")).unwrap().as_slice()).unwrap();
-        
-                let target_dir = cwd.join(PathBuf::from("target"));
-                let current_target_dir = match config_toml.build.target {
-                    Some(tgt) => target_dir.join(tgt),
-                    None => target_dir,
-                };
-        
-                let exe = { 
-                    let release = current_target_dir
-                        .join("release")
-                        .join(&cargo_toml.package.name);
-                    if !release.exists() {
-                        let debug = current_target_dir
-                            .join("debug")
-                            .join(cargo_toml.package.name);
-                        if !debug.exists() {
-                            panic!("neither release nor debug exist. may do `cargo build`")
-                        } else {
-                            debug
-                        }
-                    } else {
-                        release
-                    }
-                };
-        
-                let src = DeployPaths {
-                    execs: vec![exe],
-                    libs: vec![],
-                    config_files: vec![],
-                    user_files: vec![],
+/// How `deploy_artifacts` behaves, collected from command line flags
+#[derive(Default)]
+struct DeployOptions {
+    /// Print the plan instead of deploying
+    dry_run: Option<PlanFormat>,
+    /// Upload files even if the device already has an identical copy
+    force: bool,
+    /// Search paths of shared libraries the artifacts link to. Dependencies are not deployed if `None`
+    lib_search_paths: Option<Vec<PathBuf>>,
+    /// Target triple ELF files are checked against. No check if `None`
+    arch_check: Option<String>,
+    /// Used if `DeployConfig::strip` is set
+    strip_tools: StripTools
+}
+
+/// ELF files among `files` must be built for `triple` and, if `remote` is given, run on the device
+fn check_arch(files: &[PlanEntry], triple: &str, remote: Option<&mut dyn DeployAndCallRemote>) -> DeployResult<()> {
+    let uname_m = match remote {
+        Some(remote) => Some(elf::device_machine(remote)?),
+        None => None
+    };
+    let binaries = files
+        .iter()
+        .filter(|e| e.link.is_none() && matches!(e.kind, FileKind::Exec | FileKind::Lib))
+        .map(|e| e.src.as_path());
+    elf::check_arch(binaries, triple, uname_m.as_deref())
+}
+
+/// Libraries needed by `src` which are neither in `src` nor on the device.
+/// Without `remote` every library found in `search_paths` is returned
+fn missing_dependencies(src: &DeployPaths, search_paths: &[PathBuf], config: &SSHDeployConfig, remote: Option<&mut dyn DeployAndCallRemote>) -> DeployResult<DeployPaths> {
+    let roots: Vec<PathBuf> = src.execs.iter().chain(src.libs.iter()).cloned().collect();
+    let deps = elf::resolve_dependencies(&roots, search_paths)
+        .map_err(|err| DeployError::new_copy_err(Box::new(format!("can not resolve shared libraries: {}", err))))?;
+
+    let device_dirs: Vec<PathBuf> = std::iter::once(config.paths.libs_path.clone())
+        .chain(config.paths.device_lib_paths.iter().cloned())
+        .collect();
+    let present = match remote {
+        Some(remote) => {
+            let device_dirs: Vec<PathBuf> = device_dirs.iter().map(|d| remote.remote_path(d)).collect();
+            let sonames = deps.found.keys().chain(deps.unresolved.iter()).cloned().collect();
+            elf::present_on_device(remote, &sonames, &device_dirs)?
+        },
+        None => BTreeSet::new()
+    };
+
+    for soname in deps.unresolved.iter().filter(|s| !present.contains(*s)) {
+        config::print::warning(config::print::MISSING_LIB, format!("{} not found in {:?}", soname, search_paths));
+    }
+
+    let mut result = DeployPaths::default();
+    for (soname, path) in deps.found.into_iter().filter(|(s, _)| !present.contains(s)) {
+        config::print::info(config::print::DEPENDENCY, format!("{} ({})", soname, path.display()));
+        result.libs.push(path);
+    }
+    Ok(result)
+}
+
+/// Unless `options.force` is set files with the same checksum on the device are not uploaded.
+/// Replaced files are backed up for `cargo condep rollback`
+fn deploy_artifacts(config_provider: &ConfigProvider, config: &SSHDeployConfig, method: &DeployMethod, src: DeployPaths, options: DeployOptions) {
+    let mut src = src;
+    let package_root = config_provider.project_dir.clone().unwrap_or_default();
+    // a dry run does not touch the artifacts, the plan shows sizes before stripping
+    let strip = if options.dry_run.is_some() { StripMode::None } else { config.paths.strip };
+    let result: DeployResult<()> = options.strip_tools.strip(&mut src, strip)
+        .and_then(|()| config.paths.files.collect(&package_root, &mut src))
+        .and_then(|()| config.paths.plan(&src))
+        .and_then(|mut files| match options.dry_run {
+        Some(format) => {
+            if let Some(search_paths) = &options.lib_search_paths {
+                let deps = missing_dependencies(&src, search_paths, config, None)?;
+                files.extend(config.paths.plan(&deps)?);
+            }
+            if let Some(triple) = &options.arch_check {
+                check_arch(&files, triple, None)?;
+            }
+            if let (DeployMethod::Local, Some(local)) = (method, &config.local) {
+                for entry in files.iter_mut() {
+                    entry.overwrites = Some(local.staged_path(&entry.dst).exists());
+                }
+            }
+            let plan = DeployPlan {
+                target: method.describe(config),
+                commands: method.hooks(config).plan(&deploy::destinations(&files), |p| method.remote_path(config, p)),
+                files
+            };
+            match format {
+                PlanFormat::Text => println!("{}", plan),
+                PlanFormat::Json => println!("{}", serde_json::to_string_pretty(&plan).unwrap()),
+            }
+            Ok(())
+        },
+        None => {
+            let mut depl = method.depl(config);
+            let device_dir = config_provider.device_dir(&method.device_id(config));
+
+            if let Some(search_paths) = &options.lib_search_paths {
+                let deps = missing_dependencies(&src, search_paths, config, Some(depl.as_mut()))?;
+                files.extend(config.paths.plan(&deps)?);
+            }
+            if let Some(triple) = &options.arch_check {
+                // a staging directory has no machine of its own
+                let remote: Option<&mut dyn DeployAndCallRemote> = match method {
+                    DeployMethod::SSH => Some(depl.as_mut()),
+                    _ => None
                 };
-        
-                println!("src: {:#?}", src);
-        
-                depl.call_remote(b"mount -o rw,remount /ebrmain").unwrap();
-        
-                let dst = depl.deploy(src, config.deploy.paths).unwrap();
-        
-                for exe in dst.execs {
-                    depl.call_remote(format!("chmod +x {:?}", exe).as_bytes()).unwrap();
-                }        
+                check_arch(&files, triple, remote)?;
+            }
+
+            let (files, skipped) = if options.force {
+                (files, Vec::new())
+            } else {
+                checksum::split_unchanged(depl.as_mut(), files)?
+            };
+
+            method.hooks(config).run_pre_deploy(depl.as_mut())
+                .and_then(|()| backup::deploy_atomic(depl.as_mut(), &files, &device_dir))
+                .and_then(|()| record_manifest(config_provider, config, depl.as_mut(), &files, &skipped, &device_dir))
+                .and_then(|()| method.hooks(config).run_post_file(&deploy::destinations(&files), depl.as_mut()))
+                .and_then(|()| method.hooks(config).run_post_deploy(depl.as_mut()))
+                .map(|()| config::print::info(config::print::DEPLOYED, format!("{}", DeployReport::new(&files, &skipped))))
+        }
+    });
+
+    if let Err(err) = result {
+        config::print::fatal(config::print::DEPLOY_FAILED, format!("{}", err))
+    }
+}
+
+/// Saves deployed files to the local manifest of the device and uploads it to `manifest_path` if configured
+fn record_manifest(config_provider: &ConfigProvider, config: &SSHDeployConfig, depl: &mut dyn DeployAndCallRemote, uploaded: &[PlanEntry], unchanged: &[PlanEntry], device_dir: &Path) -> DeployResult<()> {
+    let revision = manifest::git_revision(config_provider.project_dir.as_deref().unwrap_or_else(|| Path::new(".")));
+    let mut manifest = DeployManifest::load(device_dir)?;
+    manifest.update(uploaded, unchanged, revision, |p| depl.remote_path(p))?;
+    manifest.save(device_dir)?;
+
+    match &config.paths.manifest_path {
+        Some(dst) => depl.deploy(&[PlanEntry {
+            kind: FileKind::Config,
+            src: DeployManifest::path(device_dir),
+            dst: dst.clone(),
+            size: 0,
+            mode: DeployConfig::DEFAULT_MODE,
+            owner: None,
+            group: None,
+            link: None,
+            overwrites: None
+        }]),
+        None => Ok(())
+    }
+}
+
+#[derive(clap::Args)]
+#[clap(author, version, about, long_about = Some("Remove every file deployed to the device and run cleanup hooks"))]
+struct Uninstall {
+    #[clap(long, parse(try_from_str), default_value = "ssh")]
+    method: DeployMethod
+}
+
+impl Uninstall {
+    fn exec(self, config_provider: ConfigProvider) {
+        let config = config_provider.read_or_exit().deploy;
+        let device_dir = config_provider.device_dir(&self.method.device_id(&config));
+
+        let result = DeployManifest::load(&device_dir).and_then(|manifest| {
+            if manifest.files.is_empty() {
+                config::print::fatal(config::print::NOTHING_TO_DEPLOY, format!("nothing is installed on {}", self.method.describe(&config)))
+            }
+            let mut depl = self.method.depl(&config);
+            let device_manifest = config.paths.manifest_path.as_ref().map(|p| depl.remote_path(p));
+            self.method.hooks(&config).run_pre_deploy(depl.as_mut())
+                .and_then(|()| manifest.uninstall(depl.as_mut()))
+                .and_then(|()| match &device_manifest {
+                    Some(path) => {
+                        let cmd = format!("rm -f {}", deploy::shell_quote(&path.to_string_lossy()));
+                        depl.call_remote(cmd.as_bytes())?.check(cmd.as_bytes()).map(|_| ())
+                    },
+                    None => Ok(())
+                })
+                .and_then(|()| BackupRecord::load(&device_dir))
+                .and_then(|record| match record {
+                    Some(record) => record.discard(depl.as_mut()),
+                    None => Ok(())
+                })
+                .and_then(|()| self.method.hooks(&config).run_cleanup(depl.as_mut()))
+                .and_then(|()| DeployManifest::remove(&device_dir))
+                .and_then(|()| BackupRecord::remove(&device_dir))
+                .map(|()| config::print::info(config::print::UNINSTALLED, format!("{} files from {}", manifest.files.len(), self.method.describe(&config))))
+        });
+
+        if let Err(err) = result {
+            config::print::fatal(config::print::DEPLOY_FAILED, format!("{}", err))
+        }
+    }
+}
+
+#[derive(clap::Args)]
+#[clap(author, version, about, long_about = Some("Show differences between the deploy manifest and files on the device"))]
+struct Status {
+    #[clap(long, parse(try_from_str), default_value = "ssh")]
+    method: DeployMethod
+}
+
+impl Status {
+    fn exec(self, config_provider: ConfigProvider) {
+        let config = config_provider.read_or_exit().deploy;
+        let device_dir = config_provider.device_dir(&self.method.device_id(&config));
+
+        let result = DeployManifest::load(&device_dir).and_then(|manifest| {
+            println!("{} (revision {})", self.method.describe(&config), manifest.revision.as_deref().unwrap_or("unknown"));
+            let mut depl = self.method.depl(&config);
+            let status = manifest.status(depl.as_mut())?;
+            for (entry, status) in &status {
+                println!("  {:<8} {:<6} {}", status, entry.kind, entry.dst.display());
+            }
+            let drift = status.iter().filter(|(_, s)| *s != FileStatus::Ok).count();
+            println!("  {} files, {} changed or missing", status.len(), drift);
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            config::print::fatal(config::print::DEPLOY_FAILED, format!("{}", err))
+        }
+    }
+}
+
+#[derive(clap::Args)]
+#[clap(author, version, about, long_about = Some("Check that symbol versions the artifacts need are provided by the target sysroot and the device"))]
+struct Check {
+    #[clap(long, parse(from_str), default_value = "pretty")]
+    log_level: LogLevel,
+    #[clap(long, parse(try_from_str), default_value = "ssh")]
+    method: DeployMethod,
+    /// Profile the artifacts were built with. If not specified release is used when built, otherwise debug
+    #[clap(long)]
+    profile: Option<String>,
+    #[clap(flatten)]
+    artifacts: ArtifactArgs,
+    /// Check against the sysroot only, without connecting to the device
+    #[clap(long)]
+    no_device: bool
+}
+
+/// Prints requirements of `needs` which `defs` (soname -> defined versions) does not satisfy. Returns their count
+fn report_unsatisfied(needs: &[(PathBuf, Vec<VersionNeed>)], defs: &BTreeMap<String, BTreeSet<String>>, provider: &str) -> usize {
+    let mut count = 0;
+    for (path, needs) in needs {
+        for need in elf::unsatisfied(needs, defs) {
+            let symbols = if need.symbols.is_empty() { String::new() } else { format!(" (needed by {})", need.symbols.join(", ")) };
+            config::print::warning(config::print::INCOMPATIBLE, format!("{}: {} of {} is not provided by {}{}", path.display(), need.version, need.library, provider, symbols));
+            count += 1;
+        }
+    }
+    count
+}
+
+impl Check {
+    fn exec(self, config_provider: ConfigProvider, layout: &ProjectLayout) {
+        let config = config_provider.read_or_exit();
+        let config_toml = read_cargo_config(layout);
+        let src = built_artifacts(layout, &config_toml, self.artifacts, &self.profile);
+
+        let needs: Vec<(PathBuf, Vec<VersionNeed>)> = match src
+            .execs
+            .iter()
+            .chain(src.libs.iter())
+            .map(|path| elf::version_needs(path).map(|needs| (path.clone(), needs)).map_err(|err| format!("{:?}: {}", path, err)))
+            .collect()
+        {
+            Ok(needs) => needs,
+            Err(err) => config::print::fatal(config::print::CHECK_FAILED, err)
+        };
+        let libraries: BTreeSet<&str> = needs.iter().flat_map(|(_, needs)| needs.iter().map(|n| n.library.as_str())).collect();
+        let defs = |bytes: Result<Vec<u8>, elf::ElfError>, lib: &str| match bytes.and_then(|bytes| elf::version_defs(&bytes)) {
+            Ok(defs) => defs,
+            Err(err) => config::print::fatal(config::print::CHECK_FAILED, format!("{}: {}", lib, err))
+        };
+
+        let search_paths = config.config
+            .resolve(&config_toml.build.target, self.log_level, &layout.package_root)
+            .map(|resolved| resolved.lib_search_paths)
+            .unwrap_or_default();
+        let mut sysroot_defs = BTreeMap::new();
+        for lib in &libraries {
+            match search_paths.iter().map(|dir| dir.join(lib)).find(|p| p.is_file()) {
+                Some(path) => { sysroot_defs.insert(lib.to_string(), defs(elf::read(&path), lib)); },
+                None => config::print::warning(config::print::MISSING_LIB, format!("{} not found in {:?}", lib, search_paths)),
+            }
+        }
+        let mut offending = report_unsatisfied(&needs, &sysroot_defs, "the sysroot");
+
+        if !self.no_device {
+            let mut depl = self.method.depl(&config.deploy);
+            let dirs: Vec<PathBuf> = std::iter::once(&config.deploy.paths.libs_path)
+                .chain(config.deploy.paths.device_lib_paths.iter())
+                .map(|dir| depl.remote_path(dir))
+                .collect();
+            let mut device_defs = BTreeMap::new();
+            for lib in &libraries {
+                match elf::fetch_from_device(depl.as_mut(), lib, &dirs) {
+                    Ok(Some(bytes)) => { device_defs.insert(lib.to_string(), defs(Ok(bytes), lib)); },
+                    Ok(None) => config::print::warning(config::print::MISSING_LIB, format!("{} not found on the device in {:?}", lib, dirs)),
+                    Err(err) => config::print::fatal(config::print::DEPLOY_FAILED, format!("{}", err)),
+                }
+            }
+            offending += report_unsatisfied(&needs, &device_defs, &self.method.describe(&config.deploy));
+        }
+
+        if offending > 0 {
+            config::print::fatal(config::print::CHECK_FAILED, format!("{} symbol versions are not provided", offending))
+        }
+        config::print::info(config::print::CHECKED, format!("{} artifacts, {} libraries", needs.len(), libraries.len()))
+    }
+}
+
+#[derive(clap::Args)]
+#[clap(author, version, about, long_about = Some("Deploy an executable, start gdbserver on the device and attach the cross gdb to it"))]
+struct Debug {
+    #[clap(long, parse(from_str), default_value = "pretty")]
+    log_level: LogLevel,
+    #[clap(long, parse(try_from_str), default_value = "ssh")]
+    method: DeployMethod,
+    /// Profile the artifacts were built with. If not specified release is used when built, otherwise debug
+    #[clap(long)]
+    profile: Option<String>,
+    #[clap(flatten)]
+    artifacts: ArtifactArgs,
+    /// Port gdbserver listens on
+    #[clap(long, default_value_t = debug::DEFAULT_PORT)]
+    port: u16,
+    /// Address gdb connects to. Defaults to the SSH host
+    #[clap(long)]
+    host: Option<String>,
+    /// Add a configuration to `.vscode/launch.json` of the workspace instead of starting gdb
+    #[clap(long)]
+    vscode: bool,
+    /// Arguments of the debugged program
+    #[clap(last = true)]
+    args: Vec<String>
+}
+
+impl Debug {
+    fn exec(self, config_provider: ConfigProvider, layout: &ProjectLayout) {
+        let config = config_provider.read_or_exit();
+        let config_toml = read_cargo_config(layout);
+        let src = built_artifacts(layout, &config_toml, self.artifacts, &self.profile);
+        let program = match src.execs.as_slice() {
+            [exe] => exe.clone(),
+            _ => config::print::fatal(config::print::NOTHING_TO_DEPLOY, format!("select one executable to debug with --bin or --example: {:?}", src.execs)),
+        };
+
+        let target = config_toml.build.target.clone();
+        let resolved = match config.config.resolve(&target, self.log_level, &layout.package_root) {
+            Some(resolved) => resolved,
+            None => config::print::fatal(config::print::BAD_CONFIG, format!("undefined target {:?}", target)),
+        };
+        let env: BTreeMap<String, String> = resolved.env.iter().cloned().collect();
+
+        let options = DeployOptions {
+            arch_check: Some(target.unwrap_or_else(config::host_triple)),
+            strip_tools: StripTools::new(&env, resolved.linker.as_deref()),
+            ..Default::default()
+        };
+        deploy_artifacts(&config_provider, &config.deploy, &self.method, src, options);
+
+        let mut depl = self.method.depl(&config.deploy);
+        let remote_exe = depl.remote_path(&config.deploy.paths.execs_path.join(program.file_name().unwrap_or_default()));
+        match debug::start_gdbserver(depl.as_mut(), &remote_exe, &self.args, self.port) {
+            Ok(pid) => config::print::info(config::print::GDBSERVER, format!("{} (pid {}) on port {}", remote_exe.display(), pid, self.port)),
+            Err(err) => config::print::fatal(config::print::DEPLOY_FAILED, format!("{}", err)),
+        }
+
+        let host = self.host.unwrap_or_else(|| match self.method {
+            DeployMethod::SSH => config.deploy.ssh.host.clone(),
+            _ => "localhost".into()
+        });
+        let session = GdbSession {
+            gdb: config::toolchain_tool(&env, resolved.linker.as_deref(), "GDB", "gdb"),
+            address: format!("{}:{}", host, self.port),
+            sysroot: resolved.sysroot,
+            solib_search_path: program.parent().map(Path::to_path_buf).into_iter().chain(resolved.lib_search_paths).collect(),
+            program
+        };
+
+        if self.vscode {
+            let path = layout.workspace_root.join(".vscode").join("launch.json");
+            let name = format!("condep: {}", session.program.file_name().unwrap_or_default().to_string_lossy());
+            match session.write_launch_json(&path, &name) {
+                Ok(()) => config::print::info(config::print::LAUNCH_ADDED, format!("{:?} to {:?}", name, path)),
+                Err(err) => config::print::fatal(config::print::BAD_CONFIG, err),
+            }
+        } else {
+            use std::os::unix::process::CommandExt;
+            // replaces condep so Ctrl-C reaches gdb only
+            let err = session.command().exec();
+            config::print::fatal(config::print::BAD_STATUS, format!("can not run {:?}: {}", session.gdb, err))
+        }
+    }
+}
+
+#[derive(clap::Args)]
+#[clap(author, version, about, long_about = Some("Restore files replaced by the last deploy to the device"))]
+struct Rollback {
+    #[clap(long, parse(try_from_str), default_value = "ssh")]
+    method: DeployMethod
+}
+
+impl Rollback {
+    fn exec(self, config_provider: ConfigProvider) {
+        let config = config_provider.read_or_exit().deploy;
+        let device_dir = config_provider.device_dir(&self.method.device_id(&config));
+
+        let result = BackupRecord::load(&device_dir).and_then(|record| match record {
+            Some(record) => {
+                let mut depl = self.method.depl(&config);
+                self.method.hooks(&config).run_pre_deploy(depl.as_mut())
+                    .and_then(|()| record.restore(depl.as_mut()))
+                    .and_then(|()| BackupRecord::remove(&device_dir))
+                    .and_then(|()| self.method.hooks(&config).run_post_deploy(depl.as_mut()))
+                    .map(|()| config::print::info(config::print::ROLLED_BACK, format!("{} files on {}", record.files.len(), self.method.describe(&config))))
             },
-            Err(err) => panic!("can not read config {:?}", err)
+            None => config::print::fatal(config::print::NOTHING_TO_DEPLOY, format!("no backup for {}", self.method.describe(&config))),
+        });
+
+        if let Err(err) = result {
+            config::print::fatal(config::print::DEPLOY_FAILED, format!("{}", err))
+        }
+    }
+}
+
+#[derive(clap::Args)]
+#[clap(author, version, about, long_about = Some("Build with configured environment and optionally deploy the produced artifacts"))]
+struct Build {
+    /// Target triple. Defaults to `build.target` of `.cargo/config.toml`
+    #[clap(long, parse(from_str))]
+    target: Option<String>,
+    #[clap(long, parse(from_str), default_value = "pretty")]
+    log_level: LogLevel,
+    #[clap(long)]
+    profile: Option<String>,
+    /// Space or comma separated list of features to activate
+    #[clap(long)]
+    features: Option<String>,
+    #[clap(flatten)]
+    artifacts: ArtifactArgs,
+    /// Deploy built artifacts
+    #[clap(long)]
+    deploy: bool,
+    #[clap(long, parse(try_from_str), default_value = "ssh")]
+    method: DeployMethod,
+    /// Upload every file even if the device already has an identical copy
+    #[clap(long)]
+    force: bool,
+    /// Also deploy shared libraries the artifacts need which are missing on the device
+    #[clap(long)]
+    with_deps: bool,
+    /// Deploy binaries even if they are built for another architecture than the target or the device
+    #[clap(long)]
+    no_arch_check: bool
+}
+
+impl Build {
+    fn exec(self, config_provider: ConfigProvider, layout: &ProjectLayout) {
+        let config = config_provider.read_or_exit();
+
+        let target = self.target.or_else(|| read_cargo_config(layout).build.target);
+        let resolved = match config.config.resolve(&target, self.log_level, &layout.package_root) {
+            Some(resolved) => resolved,
+            None => config::print::fatal(config::print::BAD_CONFIG, format!("undefined target {:?}", target)),
+        };
+
+        let mut cmd = std::process::Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".into()));
+        cmd
+            .arg("build")
+            .arg("--message-format=json-render-diagnostics")
+            .arg("--manifest-path").arg(&layout.manifest_path)
+            .envs(resolved.cargo_env())
+            .stdout(std::process::Stdio::piped());
+        if let Some(target) = &resolved.target {
+            cmd.arg("--target").arg(target);
+        }
+        if let Some(profile) = &self.profile {
+            cmd.arg("--profile").arg(profile);
+        }
+        if let Some(features) = &self.features {
+            cmd.arg("--features").arg(features);
+        }
+        for bin in &self.artifacts.bins {
+            cmd.arg("--bin").arg(bin);
+        }
+        for example in &self.artifacts.examples {
+            cmd.arg("--example").arg(example);
+        }
+        if self.artifacts.lib {
+            cmd.arg("--lib");
+        }
+
+        let metadata = metadata::metadata(Some(&layout.manifest_path)).unwrap();
+        let package_ids: Vec<&String> = metadata
+            .selected_packages(&layout.manifest_path)
+            .into_iter()
+            .map(|p| &p.id)
+            .collect();
+        let selection = ArtifactSelection::from(self.artifacts);
+
+        let mut child = cmd.spawn().unwrap();
+        let mut src = DeployPaths::default();
+        for message in metadata::Message::parse_stream(std::io::BufReader::new(child.stdout.take().unwrap())) {
+            if let metadata::Message::CompilerArtifact(artifact) = message {
+                if package_ids.contains(&&artifact.package_id) {
+                    artifact.add_to(&selection, &mut src);
+                }
+            }
+        }
+
+        let exit_status = child.wait().unwrap();
+        if !exit_status.success() {
+            config::print::fatal(config::print::BAD_STATUS, format!("{}", exit_status))
+        }
+
+        if self.deploy {
+            let options = DeployOptions {
+                force: self.force,
+                lib_search_paths: if self.with_deps { Some(resolved.lib_search_paths) } else { None },
+                arch_check: if self.no_arch_check { None } else { Some(resolved.target.clone().unwrap_or_else(config::host_triple)) },
+                strip_tools: StripTools::new(&resolved.env.iter().cloned().collect(), resolved.linker.as_deref()),
+                ..Default::default()
+            };
+            deploy_artifacts(&config_provider, &config.deploy, &self.method, src, options)
+        } else {
+            eprintln!("built: {:#?}", src);
         }
     }
 }
@@ -366,13 +1151,13 @@ impl Install {
     fn exec(self) {
         let cfg_provider = ConfigProvider::default();
         if let Some(file) = self.file {            
-            println!("installing from path: {:?}", file.as_path());
+            eprintln!("installing from path: {:?}", file.as_path());
             cfg_provider.install_from_path(file.as_path())
                 .unwrap()
         } else if let Some(_) = self.url {
             panic!("url installation not implemented yet")
         } else if self.hardcode {
-            println!("installing from hardcode");
+            eprintln!("installing from hardcode");
             cfg_provider.install_from_row_data(&pb_whole_config()).unwrap()
         } else {
             panic!("specify --file='some/path' or --url='https://some.url'")
@@ -383,7 +1168,8 @@ impl Install {
 #[derive(clap::Args)]
 #[clap(author, version, about, long_about = None)]
 struct Condep {
-    #[clap(long, parse(from_os_str))]
+    /// Path to Cargo.toml
+    #[clap(long, global = true, parse(from_os_str))]
     manifest_path: Option<std::path::PathBuf>,
 
     #[clap(subcommand)]
@@ -392,11 +1178,26 @@ struct Condep {
 }
 
 impl Condep {
+    fn layout(manifest_path: Option<&Path>) -> ProjectLayout {
+        match ProjectLayout::resolve(manifest_path) {
+            Ok(layout) => layout,
+            Err(err) => config::print::fatal(config::print::BAD_MANIFEST, format!("{:?}", err)),
+        }
+    }
+
     fn exec(self) {
+        let layout = || Self::layout(self.manifest_path.as_deref());
+        let config_provider = |layout: &ProjectLayout| ConfigProvider::for_project(layout.package_root.clone());
         match self.sub {
-            CondepSubCommand::Configure(cmd) => cmd.exec(),
-            CondepSubCommand::Run(cmd) => cmd.exec(),
-            CondepSubCommand::Deploy(cmd) => cmd.exec(),
+            CondepSubCommand::Configure(cmd) => { let layout = layout(); cmd.exec(config_provider(&layout), &layout) },
+            CondepSubCommand::Build(cmd) => { let layout = layout(); cmd.exec(config_provider(&layout), &layout) },
+            CondepSubCommand::Run(cmd) => { let layout = layout(); cmd.exec(config_provider(&layout), &layout) },
+            CondepSubCommand::Deploy(cmd) => { let layout = layout(); cmd.exec(config_provider(&layout), &layout) },
+            CondepSubCommand::Rollback(cmd) => cmd.exec(config_provider(&layout())),
+            CondepSubCommand::Uninstall(cmd) => cmd.exec(config_provider(&layout())),
+            CondepSubCommand::Status(cmd) => cmd.exec(config_provider(&layout())),
+            CondepSubCommand::Check(cmd) => { let layout = layout(); cmd.exec(config_provider(&layout), &layout) },
+            CondepSubCommand::Debug(cmd) => { let layout = layout(); cmd.exec(config_provider(&layout), &layout) },
             CondepSubCommand::Install(cmd) => cmd.exec()
         }     
     }    
@@ -404,7 +1205,7 @@ impl Condep {
 
 
 fn main() {
-    println!("running condep (args: {:?})", std::env::args());
+    eprintln!("running condep (args: {:?})", std::env::args());
 
     match CargoSubCommand::parse() {
         CargoSubCommand::Condep(cmd) => cmd.exec(),
@@ -428,7 +1229,9 @@ fn main() {
             //println!("doc2: {}{:#?}{}", Fg(Blue), doc, Reset{}.fg_str());
             
 
-            let c_yaml = serde_yaml::to_string(&WholeConfig { config: c, deploy: SSHDeployConfig{ paths: d, ssh: ssh } }).unwrap();
+            let hooks = pb_default_deploy_hooks();
+
+            let c_yaml = serde_yaml::to_string(&WholeConfig { config: c, deploy: SSHDeployConfig{ paths: d, ssh: ssh, local: None, run: Default::default(), hooks } }).unwrap();
 
             println!("{}{}{}", Fg(LightYellow), c_yaml, Reset{}.fg_str());
 
@@ -0,0 +1,225 @@
+use std::{fmt::Display, path::{Path, PathBuf}, process::Command, time::SystemTime};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{deploy::{CallRemote, DeployError, DeployResult, ErrorKind, FileKind, PlanEntry, shell_quote}, checksum::{self, Algorithm}, backup::{self, ORIGINAL_SUFFIX}};
+
+/// Name of the manifest in the device directory
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// One installed file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    pub kind: FileKind,
+    pub src: PathBuf,
+    /// As addressed in remote commands
+    pub dst: PathBuf,
+    /// Empty for symlinks
+    pub sha256: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<PathBuf>,
+    /// Seconds since unix epoch
+    pub deployed_at: u64
+}
+
+/// Every file installed on a device by condep
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DeployManifest {
+    /// `git describe` of the project at the last deploy
+    pub revision: Option<String>,
+    pub files: Vec<ManifestEntry>
+}
+
+fn manifest_err(path: &Path, err: impl Display) -> DeployError {
+    DeployError::new(ErrorKind::History, Box::new(format!("{:?}: {}", path, err)))
+}
+
+/// `git describe --always --dirty` in `dir`. `None` outside of a git repository
+pub fn git_revision(dir: &Path) -> Option<String> {
+    Command::new("git")
+        .args(["describe", "--always", "--dirty"])
+        .current_dir(dir)
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map(|rev| rev.trim().to_string())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl DeployManifest {
+    pub fn path(device_dir: &Path) -> PathBuf { device_dir.join(MANIFEST_FILE_NAME) }
+
+    /// Empty manifest if nothing was deployed to the device yet
+    pub fn load(device_dir: &Path) -> DeployResult<Self> {
+        let path = Self::path(device_dir);
+        match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(bytes.as_slice()).map_err(|err| manifest_err(&path, err)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(manifest_err(&path, err))
+        }
+    }
+
+    pub fn save(&self, device_dir: &Path) -> DeployResult<()> {
+        let path = Self::path(device_dir);
+        std::fs::create_dir_all(device_dir)
+            .and_then(|()| std::fs::write(&path, serde_json::to_vec_pretty(self).unwrap()))
+            .map_err(|err| manifest_err(&path, err))
+    }
+
+    pub fn remove(device_dir: &Path) -> DeployResult<()> {
+        let path = Self::path(device_dir);
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(manifest_err(&path, err)),
+            _ => Ok(())
+        }
+    }
+
+    /// Adds or replaces entries of `uploaded` files. `unchanged` files keep their timestamps.
+    /// `remote_path` maps destinations the way `CallRemote::remote_path` does
+    pub fn update(&mut self, uploaded: &[PlanEntry], unchanged: &[PlanEntry], revision: Option<String>, remote_path: impl Fn(&Path) -> PathBuf) -> DeployResult<()> {
+        let deployed_at = now();
+        for (entry, fresh) in uploaded.iter().map(|e| (e, true)).chain(unchanged.iter().map(|e| (e, false))) {
+            let dst = remote_path(&entry.dst);
+            let existing = self.files.iter().position(|f| f.dst == dst);
+            if !fresh && existing.is_some() {
+                continue;
+            }
+            let sha256 = match entry.link {
+                Some(_) => String::new(),
+                None => Algorithm::Sha256
+                    .hash_file(&entry.src)
+                    .map_err(|err| DeployError::new_copy_err(Box::new(format!("{:?}: {}", entry.src, err))))?,
+            };
+            let new = ManifestEntry { kind: entry.kind, src: entry.src.clone(), dst, sha256, size: entry.size, link: entry.link.clone(), deployed_at };
+            match existing {
+                Some(i) => self.files[i] = new,
+                None => self.files.push(new)
+            }
+        }
+        self.revision = revision;
+        Ok(())
+    }
+
+    /// Puts back the files replaced by the first deploy and removes the rest of the files in the manifest from the device
+    pub fn uninstall<R: CallRemote + ?Sized>(&self, remote: &mut R) -> DeployResult<()> {
+        if self.files.is_empty() {
+            return Ok(());
+        }
+        let cmd = self.files
+            .iter()
+            .map(|f| format!(
+                "if [ -e {orig} ] || [ -L {orig} ]; then mv -f {orig} {dst}; else rm -f {dst}; fi",
+                orig = shell_quote(&backup::with_suffix(&f.dst, ORIGINAL_SUFFIX).to_string_lossy()),
+                dst = shell_quote(&f.dst.to_string_lossy())
+            ))
+            .collect::<Vec<_>>()
+            .join(" && ");
+        remote.call_remote(cmd.as_bytes())?.check(cmd.as_bytes()).map(|_| ())
+    }
+
+    /// Compares the manifest with files on the device
+    pub fn status<R: CallRemote + ?Sized>(&self, remote: &mut R) -> DeployResult<Vec<(&ManifestEntry, FileStatus)>> {
+        let paths: Vec<PathBuf> = self.files.iter().map(|f| f.dst.clone()).collect();
+        let sums = checksum::remote_checksums_with(remote, &paths, Algorithm::Sha256)?;
+        let exists = match &sums {
+            Some(_) => None,
+            None => Some(remote_existing(remote, &paths)?)
+        };
+
+        Ok(self.files.iter().map(|f| {
+            let status = match (&sums, &exists) {
+                (Some(sums), _) => match sums.get(&f.dst) {
+                    Some(_) if f.link.is_some() => FileStatus::Ok,
+                    Some(sum) if *sum == f.sha256 => FileStatus::Ok,
+                    Some(_) => FileStatus::Modified,
+                    None => FileStatus::Missing,
+                },
+                (None, Some(exists)) if exists.contains(&f.dst) => FileStatus::Unknown,
+                (None, _) => FileStatus::Missing,
+            };
+            (f, status)
+        }).collect())
+    }
+}
+
+/// Subset of `paths` which exist on the device
+fn remote_existing<R: CallRemote + ?Sized>(remote: &mut R, paths: &[PathBuf]) -> DeployResult<Vec<PathBuf>> {
+    let cmd = paths
+        .iter()
+        .map(|p| { let p = shell_quote(&p.to_string_lossy()); format!("if [ -e {p} ]; then echo {p}; fi", p = p) })
+        .collect::<Vec<_>>()
+        .join("; ");
+    let out = remote.call_remote(cmd.as_bytes())?.check(cmd.as_bytes())?;
+    Ok(out.stdout_str().lines().map(PathBuf::from).collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Ok,
+    /// Content differs from the deployed one
+    Modified,
+    Missing,
+    /// Exists but the device has no `sha256sum`
+    Unknown
+}
+
+impl Display for FileStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            FileStatus::Ok => "ok",
+            FileStatus::Modified => "modified",
+            FileStatus::Missing => "missing",
+            FileStatus::Unknown => "unknown",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_deploy::{LocalDeploy, LocalDeployConfig};
+
+    #[test]
+    fn uninstall_removes_only_recorded_files() {
+        let dir = std::env::temp_dir().join(format!("condep-manifest-{}", std::process::id()));
+        let (root, device_dir) = (dir.join("root"), dir.join("device"));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        std::fs::write(root.join("bin/tool"), "device").unwrap();
+        std::fs::write(root.join("bin/other"), "other").unwrap();
+        std::fs::write(dir.join("tool"), "v1").unwrap();
+        std::fs::write(dir.join("new"), "n").unwrap();
+
+        let entry = |name: &str| PlanEntry {
+            kind: FileKind::Exec, src: dir.join(name), dst: Path::new("/bin").join(name), size: 2, mode: 0o755,
+            owner: None, group: None, link: None, overwrites: None
+        };
+        let files = [entry("tool"), entry("new")];
+        let mut depl = LocalDeploy::new(LocalDeployConfig { root: root.clone(), commands: Default::default(), hooks: Default::default() }).unwrap();
+        backup::deploy_atomic(&mut depl, &files, &device_dir).unwrap();
+
+        let mut manifest = DeployManifest::default();
+        manifest.update(&files, &[], Some("v1".into()), |p| depl.remote_path(p)).unwrap();
+        manifest.save(&device_dir).unwrap();
+        let manifest = DeployManifest::load(&device_dir).unwrap();
+        let dsts: Vec<&Path> = manifest.files.iter().map(|f| f.dst.as_path()).collect();
+        assert_eq!(dsts, [Path::new("bin/tool"), Path::new("bin/new")]);
+        assert!(manifest.status(&mut depl).unwrap().iter().all(|(_, s)| *s == FileStatus::Ok));
+
+        manifest.uninstall(&mut depl).unwrap();
+        let read = |name: &str| std::fs::read_to_string(root.join("bin").join(name)).ok();
+        assert_eq!(read("tool").as_deref(), Some("device"));
+        assert_eq!(read("new"), None);
+        assert_eq!(read("other").as_deref(), Some("other"));
+        assert_eq!(read("tool.condep-orig"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::deploy::DeployPaths;
+
+#[derive(Debug)]
+pub enum MetadataError {
+    IOError(std::io::Error),
+    FromUtf8Error(std::string::FromUtf8Error),
+    JsonError(serde_json::Error),
+    CargoFailed(String)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Target {
+    pub name: String,
+    pub kind: Vec<String>,
+    pub crate_types: Vec<String>
+}
+
+impl Target {
+    fn has_kind(&self, kind: &str) -> bool { self.kind.iter().any(|k| k == kind) }
+
+    /// `cdylib` or `dylib`: the only library kinds which can be loaded on the device
+    pub fn is_dynamic_lib(&self) -> bool {
+        self.crate_types.iter().any(|t| t == "cdylib" || t == "dylib")
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Package {
+    pub id: String,
+    pub name: String,
+    pub manifest_path: PathBuf,
+    pub targets: Vec<Target>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    pub workspace_members: Vec<String>,
+    pub workspace_root: PathBuf,
+    pub target_directory: PathBuf
+}
+
+/// Which artifacts to deploy. Empty selection means all bins and dynamic libs
+#[derive(Debug, Default)]
+pub struct ArtifactSelection {
+    pub bins: Vec<String>,
+    pub examples: Vec<String>,
+    pub lib: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Exec,
+    Lib
+}
+
+impl ArtifactSelection {
+    pub fn is_empty(&self) -> bool {
+        self.bins.is_empty() && self.examples.is_empty() && !self.lib
+    }
+
+    pub fn matches(&self, target: &Target) -> Option<ArtifactKind> {
+        if target.has_kind("bin") && (self.is_empty() || self.bins.contains(&target.name)) {
+            Some(ArtifactKind::Exec)
+        } else if target.has_kind("example") && self.examples.contains(&target.name) {
+            if target.is_dynamic_lib() { Some(ArtifactKind::Lib) } else { Some(ArtifactKind::Exec) }
+        } else if target.is_dynamic_lib() && !target.has_kind("example") && (self.is_empty() || self.lib) {
+            Some(ArtifactKind::Lib)
+        } else {
+            None
+        }
+    }
+}
+
+/// Directory name of a profile inside the target dir
+pub fn profile_dir(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        other => other
+    }
+}
+
+fn dynamic_lib_file_name(target_name: &str) -> String {
+    format!("lib{}.so", target_name.replace('-', "_"))
+}
+
+impl Metadata {
+    /// The package at `manifest_path` or all workspace members if it is a virtual manifest
+    pub fn selected_packages(&self, manifest_path: &Path) -> Vec<&Package> {
+        match self.packages.iter().find(|p| p.manifest_path == manifest_path) {
+            Some(package) => vec![package],
+            None => self
+                .packages
+                .iter()
+                .filter(|p| self.workspace_members.contains(&p.id))
+                .collect()
+        }
+    }
+}
+
+/// Expected artifact locations in `profile_dir` (e.g. `target/armv7-unknown-linux-gnueabi/release`).
+/// Files are not required to exist.
+pub fn artifacts(packages: &[&Package], selection: &ArtifactSelection, profile_dir: &Path) -> DeployPaths {
+    let mut result = DeployPaths::default();
+    for target in packages.iter().flat_map(|p| p.targets.iter()) {
+        let dir = if target.has_kind("example") { profile_dir.join("examples") } else { profile_dir.to_path_buf() };
+        match selection.matches(target) {
+            Some(ArtifactKind::Exec) => result.execs.push(dir.join(&target.name)),
+            Some(ArtifactKind::Lib) => result.libs.push(dir.join(dynamic_lib_file_name(&target.name))),
+            None => {}
+        }
+    }
+    result
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Artifact {
+    pub package_id: String,
+    pub target: Target,
+    pub filenames: Vec<PathBuf>,
+    pub executable: Option<PathBuf>,
+    pub fresh: bool
+}
+
+/// Line of `cargo build --message-format=json` output
+#[derive(Deserialize, Debug)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum Message {
+    CompilerArtifact(Artifact),
+    BuildFinished { success: bool },
+    #[serde(other)]
+    Other
+}
+
+impl Message {
+    /// Non JSON lines are skipped
+    pub fn parse_stream<R: BufRead>(reader: R) -> impl Iterator<Item = Message> {
+        reader
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str(line.as_str()).ok())
+    }
+}
+
+impl Artifact {
+    pub fn add_to(&self, selection: &ArtifactSelection, paths: &mut DeployPaths) {
+        match selection.matches(&self.target) {
+            Some(ArtifactKind::Exec) => paths.execs.extend(self.executable.clone()),
+            Some(ArtifactKind::Lib) => paths.libs.extend(self
+                .filenames
+                .iter()
+                .filter(|f| f.extension().is_some_and(|e| e == "so"))
+                .cloned()
+            ),
+            None => {}
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct LocateProject {
+    root: PathBuf
+}
+
+fn cargo_json<T: for<'de> Deserialize<'de>>(args: &[&str], manifest_path: Option<&Path>) -> Result<T, MetadataError> {
+    let mut cmd = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".into()));
+    cmd.args(args);
+    if let Some(manifest_path) = manifest_path {
+        cmd.arg("--manifest-path").arg(manifest_path);
+    }
+
+    cmd.output()
+        .map_err(MetadataError::IOError)
+        .and_then(|out| if out.status.success() {
+            serde_json::from_slice(out.stdout.as_slice()).map_err(MetadataError::JsonError)
+        } else {
+            String::from_utf8(out.stderr)
+                .map_err(MetadataError::FromUtf8Error)
+                .and_then(|err| Err(MetadataError::CargoFailed(err)))
+        })
+}
+
+/// `cargo locate-project`: manifest of the package containing `manifest_path` or the current directory
+pub fn locate_project(manifest_path: Option<&Path>) -> Result<PathBuf, MetadataError> {
+    cargo_json::<LocateProject>(&["locate-project", "--message-format", "json"], manifest_path)
+        .map(|l| l.root)
+}
+
+/// `cargo metadata --no-deps` (packages are workspace members only)
+pub fn metadata(manifest_path: Option<&Path>) -> Result<Metadata, MetadataError> {
+    cargo_json(&["metadata", "--no-deps", "--format-version", "1"], manifest_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `cargo build --message-format=json` of a package with a bin, an example, a cdylib and an rlib
+    const STREAM: &str = r#"{"reason":"compiler-artifact","package_id":"app 0.1.0","target":{"name":"tool","kind":["bin"],"crate_types":["bin"]},"filenames":["/t/debug/tool"],"executable":"/t/debug/tool","fresh":false}
+   Compiling app v0.1.0 (/app)
+{"reason":"compiler-artifact","package_id":"app 0.1.0","target":{"name":"demo","kind":["example"],"crate_types":["bin"]},"filenames":["/t/debug/examples/demo"],"executable":"/t/debug/examples/demo","fresh":true}
+{"reason":"compiler-artifact","package_id":"app 0.1.0","target":{"name":"plug","kind":["cdylib"],"crate_types":["cdylib"]},"filenames":["/t/debug/libplug.so","/t/debug/libplug.d"],"executable":null,"fresh":false}
+{"reason":"compiler-artifact","package_id":"app 0.1.0","target":{"name":"core","kind":["lib"],"crate_types":["lib"]},"filenames":["/t/debug/libcore.rlib"],"executable":null,"fresh":false}
+{"reason":"build-finished","success":true}
+"#;
+
+    fn select(selection: ArtifactSelection) -> DeployPaths {
+        let mut paths = DeployPaths::default();
+        for message in Message::parse_stream(STREAM.as_bytes()) {
+            if let Message::CompilerArtifact(artifact) = message {
+                artifact.add_to(&selection, &mut paths);
+            }
+        }
+        paths
+    }
+
+    #[test]
+    fn stream_skips_lines_which_are_not_messages() {
+        let messages: Vec<Message> = Message::parse_stream(STREAM.as_bytes()).collect();
+        assert_eq!(messages.len(), 5);
+        assert!(matches!(messages[4], Message::BuildFinished { success: true }));
+    }
+
+    #[test]
+    fn nothing_selected_means_bins_and_dynamic_libs() {
+        let paths = select(ArtifactSelection::default());
+        assert_eq!(paths.execs, [PathBuf::from("/t/debug/tool")]);
+        assert_eq!(paths.libs, [PathBuf::from("/t/debug/libplug.so")]);
+    }
+
+    #[test]
+    fn bin_example_and_lib_flags_pick_only_their_artifacts() {
+        let bins = select(ArtifactSelection { bins: vec!["tool".into()], ..Default::default() });
+        assert_eq!((bins.execs, bins.libs), (vec![PathBuf::from("/t/debug/tool")], vec![]));
+
+        let examples = select(ArtifactSelection { examples: vec!["demo".into()], ..Default::default() });
+        assert_eq!((examples.execs, examples.libs), (vec![PathBuf::from("/t/debug/examples/demo")], vec![]));
+
+        let lib = select(ArtifactSelection { lib: true, ..Default::default() });
+        assert_eq!((lib.execs, lib.libs), (vec![], vec![PathBuf::from("/t/debug/libplug.so")]));
+
+        assert!(select(ArtifactSelection { bins: vec!["other".into()], ..Default::default() }).execs.is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+use std::path::{Path, PathBuf};
+
+use serde_yaml::Value;
+
+use crate::metadata::{self, MetadataError};
+
+pub const CONFIG_FILE_NAME: &str = "condep.yaml";
+pub const MANIFEST_FILE_NAME: &str = "Cargo.toml";
+pub const METADATA_KEY: &str = "condep";
+
+#[derive(Debug)]
+pub enum ProjectConfigSource {
+    Yaml(PathBuf),
+    PackageMetadata(PathBuf),
+    WorkspaceMetadata(PathBuf)
+}
+
+#[derive(Debug)]
+pub enum ProjectConfigError {
+    IOError(std::io::Error),
+    TomlError(toml::de::Error),
+    YamlError(serde_yaml::Error)
+}
+
+#[derive(Debug)]
+pub struct ProjectConfig {
+    pub source: ProjectConfigSource,
+    pub value: Value
+}
+
+impl ProjectConfig {
+    fn from_yaml(path: PathBuf) -> Result<Self, ProjectConfigError> {
+        std::fs::read(&path)
+            .map_err(ProjectConfigError::IOError)
+            .and_then(|bytes| serde_yaml::from_slice(bytes.as_slice())
+                .map_err(ProjectConfigError::YamlError)
+            )
+            .map(|value| ProjectConfig { source: ProjectConfigSource::Yaml(path), value })
+    }
+
+    /// Returns `None` when the manifest has neither `[package.metadata.condep]` nor `[workspace.metadata.condep]`
+    fn from_manifest(path: PathBuf) -> Result<Option<Self>, ProjectConfigError> {
+        let manifest: toml::Value = std::fs::read(&path)
+            .map_err(ProjectConfigError::IOError)
+            .and_then(|bytes| toml::from_slice(bytes.as_slice())
+                .map_err(ProjectConfigError::TomlError)
+            )?;
+
+        let metadata = |section: &str| manifest
+            .get(section)
+            .and_then(|s| s.get("metadata"))
+            .and_then(|m| m.get(METADATA_KEY))
+            .cloned();
+
+        let (source, value) = match (metadata("package"), metadata("workspace")) {
+            (Some(v), _) => (ProjectConfigSource::PackageMetadata(path), v),
+            (None, Some(v)) => (ProjectConfigSource::WorkspaceMetadata(path), v),
+            (None, None) => return Ok(None)
+        };
+
+        serde_yaml::to_value(value)
+            .map_err(ProjectConfigError::YamlError)
+            .map(|value| Some(ProjectConfig { source, value }))
+    }
+
+    /// Walks up from `start` and returns the nearest project config.
+    /// In each directory `condep.yaml` takes priority over `Cargo.toml` metadata.
+    pub fn discover(start: &Path) -> Result<Option<Self>, ProjectConfigError> {
+        for dir in start.ancestors() {
+            let yaml = dir.join(CONFIG_FILE_NAME);
+            if yaml.is_file() {
+                return Self::from_yaml(yaml).map(Some);
+            }
+            let manifest = dir.join(MANIFEST_FILE_NAME);
+            if manifest.is_file() {
+                if let Some(config) = Self::from_manifest(manifest)? {
+                    return Ok(Some(config));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Overrides `base` with `top` field by field.
+/// Mappings are merged recursively, any other value (including sequences) is replaced as a whole.
+pub fn merge_yaml(base: &mut Value, top: Value) {
+    match (base, top) {
+        (Value::Mapping(base), Value::Mapping(top)) => {
+            for (k, v) in top {
+                match base.get_mut(&k) {
+                    Some(b) => merge_yaml(b, v),
+                    None => { base.insert(k, v); }
+                }
+            }
+        },
+        (base, top) => *base = top
+    }
+}
+
+/// Package, workspace and target locations resolved the same way cargo does it
+#[derive(Debug, Clone)]
+pub struct ProjectLayout {
+    pub manifest_path: PathBuf,
+    pub package_root: PathBuf,
+    pub workspace_root: PathBuf,
+    pub target_dir: PathBuf
+}
+
+impl ProjectLayout {
+    /// Uses `manifest_path` if present, otherwise searches for `Cargo.toml` from the current directory
+    pub fn resolve(manifest_path: Option<&Path>) -> Result<Self, MetadataError> {
+        let manifest_path = metadata::locate_project(manifest_path)?;
+        let metadata = metadata::metadata(Some(manifest_path.as_path()))?;
+        Ok(ProjectLayout {
+            package_root: manifest_path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            manifest_path,
+            workspace_root: metadata.workspace_root,
+            target_dir: metadata.target_directory
+        })
+    }
+
+    /// Config file of the workspace root, which cargo reads for every member. See `cargo_config_in`
+    pub fn cargo_config_path(&self) -> PathBuf {
+        cargo_config_in(&self.workspace_root)
+    }
+}
+
+/// Legacy `.cargo/config` of `dir` if it exists (cargo prefers it when both exist), `.cargo/config.toml` otherwise
+pub fn cargo_config_in(dir: &Path) -> PathBuf {
+    let legacy = dir.join(".cargo").join("config");
+    if legacy.is_file() { legacy } else { dir.join(".cargo").join("config.toml") }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(s: &str) -> Value {
+        serde_yaml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn nearest_config_wins_and_condep_yaml_beats_metadata() {
+        let ws = std::env::temp_dir().join(format!("condep-discover-{}", std::process::id()));
+        let member = ws.join("member");
+        let src = member.join("src");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(ws.join(MANIFEST_FILE_NAME), "[workspace]\nmembers = [\"member\"]\n[workspace.metadata.condep]\nfrom = \"workspace\"\n").unwrap();
+        std::fs::write(member.join(MANIFEST_FILE_NAME), "[package]\nname = \"member\"\n").unwrap();
+        let from = |config: ProjectConfig| config.value.get("from").and_then(Value::as_str).map(str::to_string);
+
+        let config = ProjectConfig::discover(&src).unwrap().unwrap();
+        assert!(matches!(config.source, ProjectConfigSource::WorkspaceMetadata(_)));
+        assert_eq!(from(config).as_deref(), Some("workspace"));
+
+        std::fs::write(member.join(MANIFEST_FILE_NAME), "[package]\nname = \"member\"\n[package.metadata.condep]\nfrom = \"package\"\n").unwrap();
+        let config = ProjectConfig::discover(&src).unwrap().unwrap();
+        assert!(matches!(config.source, ProjectConfigSource::PackageMetadata(_)));
+        assert_eq!(from(config).as_deref(), Some("package"));
+
+        std::fs::write(member.join(CONFIG_FILE_NAME), "from: yaml\n").unwrap();
+        let config = ProjectConfig::discover(&src).unwrap().unwrap();
+        assert!(matches!(config.source, ProjectConfigSource::Yaml(_)));
+        assert_eq!(from(config).as_deref(), Some("yaml"));
+
+        std::fs::write(member.join(CONFIG_FILE_NAME), "from: [").unwrap();
+        assert!(matches!(ProjectConfig::discover(&src), Err(ProjectConfigError::YamlError(_))));
+
+        std::fs::remove_dir_all(&ws).unwrap();
+    }
+
+    #[test]
+    fn merge_overrides_nested_fields_and_replaces_sequences() {
+        let mut base = yaml("deploy:\n  ssh:\n    user: root\n    host: 10.0.0.1\n  pre_deploy: [a, b]\nconfig: 1\n");
+        merge_yaml(&mut base, yaml("deploy:\n  ssh:\n    host: 192.168.0.2\n  pre_deploy: [c]\nextra: x\n"));
+        assert_eq!(base, yaml("deploy:\n  ssh:\n    user: root\n    host: 192.168.0.2\n  pre_deploy: [c]\nconfig: 1\nextra: x\n"));
+    }
+
+    #[test]
+    fn cargo_config_prefers_the_legacy_name() {
+        let ws = std::env::temp_dir().join(format!("condep-project-{}", std::process::id()));
+        std::fs::create_dir_all(ws.join(".cargo")).unwrap();
+
+        assert_eq!(cargo_config_in(&ws), ws.join(".cargo").join("config.toml"));
+        std::fs::write(ws.join(".cargo").join("config.toml"), "").unwrap();
+        assert_eq!(cargo_config_in(&ws), ws.join(".cargo").join("config.toml"));
+        std::fs::write(ws.join(".cargo").join("config"), "").unwrap();
+        assert_eq!(cargo_config_in(&ws), ws.join(".cargo").join("config"));
+
+        std::fs::remove_dir_all(&ws).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use std::{path::{Path, PathBuf}, process::Command};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{deploy::{CallRemote, Deploy, DeployConfig, DeployError, DeployResult, FileKind, PlanEntry, shell_quote}, elf::ElfArch};
+
+/// How `cargo condep run --remote` runs executables on the device
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RemoteRunConfig {
+    /// Executables are uploaded here and removed after they exit
+    pub scratch_dir: PathBuf,
+    /// Local env variables passed to the executable. A trailing `*` matches by prefix
+    pub forward_env: Vec<String>
+}
+
+impl Default for RemoteRunConfig {
+    fn default() -> Self {
+        RemoteRunConfig {
+            scratch_dir: PathBuf::from("/tmp/condep-run"),
+            forward_env: vec!["RUST_*".into()]
+        }
+    }
+}
+
+impl RemoteRunConfig {
+    /// Variables among `vars` matching `forward_env`
+    pub fn forwarded_env(&self, vars: impl Iterator<Item = (String, String)>) -> Vec<(String, String)> {
+        vars
+            .filter(|(k, _)| self.forward_env.iter().any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => k.starts_with(prefix),
+                None => k == pattern,
+            }))
+            .collect()
+    }
+
+    /// Uploads `exe` to `scratch_dir`, runs it there with `args` and `env` streaming its output and removes it.
+    /// Returns the exit status of `exe`
+    pub fn run<D: Deploy + CallRemote + ?Sized>(&self, depl: &mut D, exe: &Path, args: &[String], env: &[(String, String)]) -> DeployResult<i32> {
+        let name = exe
+            .file_name()
+            .ok_or_else(|| DeployError::new_copy_err(Box::new(format!("{:?} is not a file", exe))))?;
+        let size = exe
+            .metadata()
+            .map_err(|err| DeployError::new_copy_err(Box::new(format!("{:?}: {}", exe, err))))?
+            .len();
+        depl.deploy(&[PlanEntry {
+            kind: FileKind::Exec,
+            src: exe.to_path_buf(),
+            dst: self.scratch_dir.join(name),
+            size,
+            mode: DeployConfig::EXEC_MODE,
+            owner: None,
+            group: None,
+            link: None,
+            overwrites: None
+        }])?;
+
+        let quote = |p: &Path| shell_quote(&p.to_string_lossy());
+        let env: Vec<String> = env.iter().map(|(k, v)| shell_quote(&format!("{}={}", k, v))).collect();
+        let args: Vec<String> = args.iter().map(|a| shell_quote(a)).collect();
+        let cmd = format!(
+            "cd {dir} && env {env} ./{name} {args}; status=$?; rm -f ./{name}; exit $status",
+            dir = quote(&depl.remote_path(&self.scratch_dir)),
+            env = env.join(" "),
+            name = quote(Path::new(name)),
+            args = args.join(" ")
+        );
+        depl.call_remote_streaming(cmd.as_bytes())
+    }
+}
+
+/// `qemu-<arch>` user mode emulator running `exe` with `args`. Shared libraries are loaded from `sysroot`.
+/// `ld_library_path` is set for the emulated program only so it does not affect the emulator itself
+pub fn qemu_command(exe: &Path, args: &[String], sysroot: Option<&Path>, ld_library_path: Option<&str>) -> Result<Command, String> {
+    let arch = match ElfArch::of_file(exe).map_err(|err| format!("{:?}: {}", exe, err))? {
+        Some(arch) => arch,
+        None => return Err(format!("{:?} is not an ELF file", exe))
+    };
+    let qemu = arch.qemu_arch().ok_or_else(|| format!("no qemu user mode emulator for {}", arch))?;
+
+    let mut cmd = Command::new(format!("qemu-{}", qemu));
+    if let Some(sysroot) = sysroot {
+        cmd.arg("-L").arg(sysroot);
+    }
+    if let Some(ld_library_path) = ld_library_path {
+        cmd.arg("-E").arg(format!("LD_LIBRARY_PATH={}", ld_library_path));
+    }
+    cmd.env_remove("LD_LIBRARY_PATH").arg(exe).args(args);
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(names: &[&str]) -> impl Iterator<Item = (String, String)> {
+        names.iter().map(|n| (n.to_string(), "1".to_string())).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn forward_env_matches_names_and_prefixes() {
+        let config = RemoteRunConfig { forward_env: vec!["RUST_*".into(), "QT_DEBUG".into()], ..Default::default() };
+        let names: Vec<String> = config
+            .forwarded_env(vars(&["RUST_LOG", "RUST_BACKTRACE", "RUSTFLAGS", "QT_DEBUG", "QT_DEBUG_PLUGINS", "HOME"]))
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(names, ["RUST_LOG", "RUST_BACKTRACE", "QT_DEBUG"]);
+
+        let all = RemoteRunConfig { forward_env: vec!["*".into()], ..Default::default() };
+        assert_eq!(all.forwarded_env(vars(&["A", "B"])).len(), 2);
+        let none = RemoteRunConfig { forward_env: vec![], ..Default::default() };
+        assert!(none.forwarded_env(vars(&["RUST_LOG"])).is_empty());
+    }
+}
@@ -1,81 +1,318 @@
 
-use std::{io::{Write, Read}};
+use std::{io::{Write, Read}, fmt::Display, path::{Path, PathBuf, Component}, ffi::OsStr};
 
 use serde::{Serialize, Deserialize};
 
-use crate::deploy::{Deploy, DeployConfig, DeployResult, DeployError, CallRemote, DeployPaths};
+use crate::{deploy::{Deploy, DeployResult, DeployError, CallRemote, PlanEntry, RemoteOutput, ErrorKind, shell_quote}, config::print};
 
 
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// Keys of the running ssh-agent (`SSH_AUTH_SOCK`), then `identity_file` or default keys from `~/.ssh`.
+    /// libssh has no agent only method, so `agent` is accepted as another name for it
+    #[serde(alias = "agent")]
+    PublicKey,
+    /// Password from `password_env` or `password_file`
+    Password,
+    /// Prompts on the terminal. Skipped when stdin is not a tty
+    KeyboardInteractive
+}
+
+/// How the server key is checked against known_hosts
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HostKeyPolicy {
+    /// Unknown or changed keys are rejected
+    Strict,
+    /// Unknown keys are added to known_hosts, changed keys are rejected
+    #[default]
+    AcceptNew,
+    /// No verification
+    Off
+}
+
+fn default_auth() -> Vec<AuthMethod> {
+    vec![AuthMethod::PublicKey, AuthMethod::Password, AuthMethod::KeyboardInteractive]
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SSHUserAndHost {
     pub user: String,
-    pub host: String
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_file: Option<PathBuf>,
+    /// Name of env variable holding the password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_env: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+    /// Authentication methods in the order they are tried
+    #[serde(default = "default_auth")]
+    pub auth: Vec<AuthMethod>,
+    /// `~/.ssh/known_hosts` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub known_hosts: Option<PathBuf>,
+    #[serde(default)]
+    pub host_key_policy: HostKeyPolicy
+}
+
+impl Default for SSHUserAndHost {
+    fn default() -> Self {
+        SSHUserAndHost {
+            user: String::new(),
+            host: String::new(),
+            port: None,
+            identity_file: None,
+            password_env: None,
+            password_file: None,
+            auth: default_auth(),
+            known_hosts: None,
+            host_key_policy: HostKeyPolicy::default()
+        }
+    }
+}
+
+impl SSHUserAndHost {
+    /// `None` if neither `password_env` nor `password_file` is set
+    fn password(&self) -> Option<Result<String, ssh::Error>> {
+        match (&self.password_env, &self.password_file) {
+            (Some(var), _) => Some(std::env::var(var)
+                .map_err(|err| ssh::Error::Ssh(format!("{}: {}", var, err)))),
+            (None, Some(file)) => Some(std::fs::read_to_string(file)
+                .map(|p| p.trim_end_matches(['\r', '\n']).to_string())
+                .map_err(ssh::Error::IO)),
+            (None, None) => None
+        }
+    }
 }
 
+/// Mode of remote directories created during upload
+const DIR_MODE: usize = 0o755;
+
 pub struct SSHDeploy {
     session: ssh::Session
 }
 
 
 impl SSHDeploy {
-    pub fn connect(user_and_host: &SSHUserAndHost) -> Result<SSHDeploy, ssh::Error> {
+    pub fn connect(user_and_host: &SSHUserAndHost) -> DeployResult<SSHDeploy> {
+        let connect_err = |err: ssh::Error| DeployError::new(ErrorKind::Connect, Box::new(err));
+
         let mut session = ssh::Session::new()
-            .map_err(|()| ssh::Error::Ssh(String::from("can not create ssh session")))?;
+            .map_err(|()| connect_err(ssh::Error::Ssh(String::from("can not create ssh session"))))?;
 
-        session.set_host(user_and_host.host.as_str())?;
-        session.set_username(user_and_host.user.as_str())?;
-        session.parse_config(None)?;
-        session.connect()?;
-        session.userauth_kbdint(None)?;
+        session.set_host(user_and_host.host.as_str()).map_err(connect_err)?;
+        session.set_username(user_and_host.user.as_str()).map_err(connect_err)?;
+        if let Some(port) = user_and_host.port {
+            session.set_port(port as usize).map_err(connect_err)?;
+        }
+        if let Some(identity) = &user_and_host.identity_file {
+            session.set_identity(identity).map_err(connect_err)?;
+        }
+        if let Some(known_hosts) = &user_and_host.known_hosts {
+            session.set_knownhosts(known_hosts).map_err(connect_err)?;
+        }
+        session.parse_config(None).map_err(connect_err)?;
+        session.connect().map_err(connect_err)?;
+        Self::verify_host_key(&mut session, user_and_host)?;
+        Self::authenticate(&mut session, user_and_host).map_err(connect_err)?;
         
         Ok(SSHDeploy{ session: session })
     }
+
+    fn fingerprint(session: &mut ssh::Session) -> String {
+        session
+            .get_pubkey_hash()
+            .map(|hash| hash.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":"))
+            .unwrap_or_else(|_| "<unknown>".into())
+    }
+
+    fn verify_host_key(session: &mut ssh::Session, user_and_host: &SSHUserAndHost) -> DeployResult<()> {
+        if user_and_host.host_key_policy == HostKeyPolicy::Off {
+            return Ok(());
+        }
+        let host_key_err = |msg: String| DeployError::new(ErrorKind::HostKey, Box::new(msg));
+        let known_hosts = user_and_host.known_hosts.clone().unwrap_or_else(|| PathBuf::from("~/.ssh/known_hosts"));
+
+        match session.is_server_known().map_err(|err| DeployError::new(ErrorKind::Connect, Box::new(err)))? {
+            ssh::ServerKnown::Known => Ok(()),
+            ssh::ServerKnown::Changed => Err(host_key_err(format!(
+                "host key of {} has changed (now {}). If the device was reflashed remove its entry from {:?}",
+                user_and_host.host, Self::fingerprint(session), known_hosts
+            ))),
+            ssh::ServerKnown::FoundOther => Err(host_key_err(format!(
+                "{} presented a key of another type than recorded in {:?}. If the device was reflashed remove its entry",
+                user_and_host.host, known_hosts
+            ))),
+            ssh::ServerKnown::NotKnown | ssh::ServerKnown::FileNotFound => match user_and_host.host_key_policy {
+                HostKeyPolicy::AcceptNew => {
+                    print::warning(print::HOST_KEY_ADDED, format!("{} {} -> {:?}", user_and_host.host, Self::fingerprint(session), known_hosts));
+                    session.write_knownhost().map_err(|err| host_key_err(format!("can not write {:?}: {}", known_hosts, err)))
+                },
+                _ => Err(host_key_err(format!(
+                    "host key of {} ({}) is not in {:?} and host_key_policy is strict",
+                    user_and_host.host, Self::fingerprint(session), known_hosts
+                ))),
+            },
+        }
+    }
+
+    /// Tries `user_and_host.auth` methods in order until one succeeds
+    fn authenticate(session: &mut ssh::Session, user_and_host: &SSHUserAndHost) -> Result<(), ssh::Error> {
+        let mut failures: Vec<String> = Vec::new();
+        for (i, method) in user_and_host.auth.iter().enumerate() {
+            // `agent` and `public_key` in one list are the same method
+            if user_and_host.auth[..i].contains(method) {
+                continue;
+            }
+            let result = match method {
+                AuthMethod::PublicKey => session.userauth_publickey_auto(None),
+                AuthMethod::Password => match user_and_host.password() {
+                    Some(Ok(password)) => session.userauth_password(password.as_str()),
+                    Some(Err(err)) => Err(err),
+                    None => continue,
+                },
+                AuthMethod::KeyboardInteractive => if termion::is_tty(&std::io::stdin()) {
+                    session.userauth_kbdint(None)
+                } else {
+                    failures.push("keyboard_interactive: stdin is not a tty".into());
+                    continue
+                },
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) => failures.push(format!("{:?}: {}", method, err)),
+            }
+        }
+        Err(ssh::Error::Ssh(format!("authentication failed ({})", failures.join("; "))))
+    }
 }
 
 
 impl Deploy for SSHDeploy {
-    fn deploy(&mut self, src: DeployPaths, conf: DeployConfig) -> DeployResult<DeployPaths> {
-        conf.copy_files(src, &mut |src, dst| {
-            let dstdir = if dst.is_file() { dst.parent().unwrap() } else { dst };
-            let file_name = if dst.is_file() { dst.file_name().unwrap() } else { src.file_name().unwrap() };
+    /// Regular files go through recursive SCP sessions which create missing directories on the way,
+    /// one for absolute destinations and one for destinations relative to the home directory.
+    /// Symlinks are created with `ln -s` afterwards
+    fn deploy(&mut self, files: &[PlanEntry]) -> DeployResult<()> {
+        let (links, files): (Vec<&PlanEntry>, Vec<&PlanEntry>) = files.iter().partition(|e| e.link.is_some());
+        let (absolute, relative): (Vec<&PlanEntry>, Vec<&PlanEntry>) = files.into_iter().partition(|e| e.dst.is_absolute());
 
-            println!("Coping: {} -> {}", src.to_str().unwrap(), dstdir.join(file_name).to_str().unwrap());
+        self.upload(absolute, "/")?;
+        self.upload(relative, ".")?;
 
-            let buf = std::fs::read(src)
-                .map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
-            
-            {
-                let mut scp = self.session.scp_new(ssh::WRITE,dstdir)
-                    .map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
+        if !links.is_empty() {
+            let cmd = links
+                .iter()
+                .map(|e| {
+                    let dst = shell_quote(&e.dst.to_string_lossy());
+                    let target = shell_quote(&e.link.as_ref().unwrap().to_string_lossy());
+                    format!("mkdir -p \"$(dirname {dst})\" && ln -sfn {target} {dst}", dst = dst, target = target)
+                })
+                .collect::<Vec<_>>()
+                .join(" && ");
+            self.call_remote(cmd.as_bytes())?.check(cmd.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl SSHDeploy {
+    fn remote_call_err(err: impl Display + 'static) -> DeployError {
+        DeployError::new_remote_call_err(Box::new(err))
+    }
 
-                scp.init()
-                    .map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
+    /// Copies `files` in one SCP session with destinations resolved against `location`
+    fn upload(&mut self, mut files: Vec<&PlanEntry>, location: &str) -> DeployResult<()> {
+        if files.is_empty() {
+            return Ok(());
+        }
+        files.sort_by(|a, b| a.dst.cmp(&b.dst));
+        let mut scp = self.session.scp_new(ssh::WRITE | ssh::RECURSIVE, location).map_err(Self::copy_err)?;
+        scp.init().map_err(Self::copy_err)?;
 
-                scp.push_file(file_name,buf.len(),0o644)
-                    .map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
+        // remote directory the session is in, relative to `location`
+        let mut cwd: Vec<&OsStr> = Vec::new();
+        for entry in files {
+            eprintln!("Coping: {} -> {}", entry.src.to_str().unwrap(), entry.dst.to_str().unwrap());
 
-                scp.write(&buf)
-                    .map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
+            let dir: Vec<&OsStr> = entry.dst
+                .parent()
+                .into_iter()
+                .flat_map(Path::components)
+                .filter_map(|c| match c { Component::Normal(c) => Some(c), _ => None })
+                .collect();
+            let common = cwd.iter().zip(dir.iter()).take_while(|(a, b)| a == b).count();
+            for _ in common..cwd.len() {
+                scp.leave_directory().map_err(Self::copy_err)?;
             }
+            cwd.truncate(common);
+            for name in &dir[common..] {
+                scp.push_directory(name, DIR_MODE).map_err(Self::copy_err)?;
+                cwd.push(name);
+            }
+
+            let buf = std::fs::read(&entry.src).map_err(Self::copy_err)?;
+            scp.push_file(entry.dst.file_name().unwrap(), buf.len(), entry.mode as usize).map_err(Self::copy_err)?;
+            scp.write_all(&buf).map_err(Self::copy_err)?;
+        }
+        Ok(())
+    }
 
-            Ok(dstdir.join(file_name))
-        })
+    fn copy_err(err: impl Display + 'static) -> DeployError {
+        DeployError::new_copy_err(Box::new(err))
+    }
+}
+
+impl SSHDeploy {
+    /// Runs `cmd` on a new channel. libssh buffers stderr data which arrives while stdout is read, so the streams are read one after the other
+    fn exec(&mut self, cmd: &[u8]) -> DeployResult<RemoteOutput> {
+        let mut s = self.session.channel_new().map_err(Self::remote_call_err)?;
+
+        s.open_session().map_err(Self::remote_call_err)?;
+        s.request_exec(cmd).map_err(Self::remote_call_err)?;
+        s.send_eof().map_err(Self::remote_call_err)?;
+
+        let mut output = RemoteOutput::default();
+        s.stdout().read_to_end(&mut output.stdout).map_err(Self::remote_call_err)?;
+        s.stderr().read_to_end(&mut output.stderr).map_err(Self::remote_call_err)?;
+        output.status = s.get_exit_status().unwrap_or(-1);
+        s.close();
+
+        Ok(output)
     }
 }
 
 impl CallRemote for SSHDeploy {
-    fn call_remote(&mut self, cmd: &[u8]) -> DeployResult<()> {
-        println!("running cmd: {:?}", String::from_utf8(Vec::from(cmd)));
-        let mut s = self.session.channel_new().map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
+    fn call_remote(&mut self, cmd: &[u8]) -> DeployResult<RemoteOutput> {
+        eprintln!("running cmd: {:?}", String::from_utf8_lossy(cmd));
+        self.exec(cmd)
+    }
 
-        s.open_session().map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
-        s.request_exec(cmd).map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
-        s.send_eof().map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
-        let mut buf=Vec::new();
-        s.stdout().read_to_end(&mut buf).map_err(|err| DeployError::new_copy_err(Box::new(err)))?;
+    /// stderr is redirected to stdout on the remote side so that both are forwarded live and in order
+    fn call_remote_streaming(&mut self, cmd: &[u8]) -> DeployResult<i32> {
+        let cmd = [b"(".as_slice(), cmd, b") 2>&1"].concat();
+        let mut s = self.session.channel_new().map_err(Self::remote_call_err)?;
 
-        println!("{:?}",std::str::from_utf8(&buf).unwrap());
-        Ok(())
+        s.open_session().map_err(Self::remote_call_err)?;
+        s.request_exec(&cmd).map_err(Self::remote_call_err)?;
+        s.send_eof().map_err(Self::remote_call_err)?;
+
+        let mut stdout = std::io::stdout();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = s.stdout().read(&mut buf).map_err(Self::remote_call_err)?;
+            if n == 0 {
+                break;
+            }
+            stdout.write_all(&buf[..n]).and_then(|()| stdout.flush()).map_err(Self::remote_call_err)?;
+        }
+        let status = s.get_exit_status().unwrap_or(-1);
+        s.close();
+
+        Ok(status)
     }
-}
\ No newline at end of file
+}
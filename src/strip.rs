@@ -0,0 +1,117 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}, process::Command};
+
+use crate::{config::{self, print}, deploy::{DeployError, DeployPaths, DeployResult, ErrorKind, StripMode}, elf::ElfArch};
+
+/// Stripped artifacts and their debug info are written to this directory next to the artifacts
+pub const STRIP_DIR_NAME: &str = "condep-stripped";
+/// Suffix of the file debug info is split into
+pub const DEBUG_SUFFIX: &str = ".debug";
+
+fn strip_err(path: &Path, err: impl std::fmt::Display) -> DeployError {
+    DeployError::new(ErrorKind::Strip, Box::new(format!("{:?}: {}", path, err)))
+}
+
+/// Cross `strip` and `objcopy` of the toolchain
+#[derive(Debug, Clone)]
+pub struct StripTools {
+    pub strip: PathBuf,
+    pub objcopy: PathBuf
+}
+
+impl Default for StripTools {
+    /// Host tools from `PATH`
+    fn default() -> Self {
+        StripTools { strip: PathBuf::from("strip"), objcopy: PathBuf::from("objcopy") }
+    }
+}
+
+impl StripTools {
+    /// `STRIP` and `OBJCOPY` of the toolchain `env`, otherwise derived from the linker. See `config::toolchain_tool`
+    pub fn new(env: &BTreeMap<String, String>, linker: Option<&str>) -> Self {
+        StripTools {
+            strip: config::toolchain_tool(env, linker, "STRIP", "strip"),
+            objcopy: config::toolchain_tool(env, linker, "OBJCOPY", "objcopy")
+        }
+    }
+
+    fn run(&self, program: &Path, args: &[&std::ffi::OsStr], file: &Path) -> DeployResult<()> {
+        let out = Command::new(program)
+            .args(args)
+            .output()
+            .map_err(|err| strip_err(file, format!("can not run {:?}: {}", program, err)))?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(strip_err(file, format!("{:?} {}: {}", program, out.status, String::from_utf8_lossy(&out.stderr).trim_end())))
+        }
+    }
+
+    /// Writes `src` stripped according to `mode` into `STRIP_DIR_NAME` next to it. Debug info goes to
+    /// `<name>.debug` in the same directory and is referenced by `.gnu_debuglink` of the stripped file.
+    /// Returns the stripped file
+    pub fn strip_file(&self, src: &Path, mode: StripMode) -> DeployResult<PathBuf> {
+        let name = src.file_name().ok_or_else(|| strip_err(src, "not a file"))?;
+        let dir = src.parent().unwrap_or_else(|| Path::new(".")).join(STRIP_DIR_NAME);
+        let dst = dir.join(name);
+        let mut debug = dst.clone().into_os_string();
+        debug.push(DEBUG_SUFFIX);
+        let debug = PathBuf::from(debug);
+
+        let flag = match mode {
+            StripMode::None => return Ok(src.to_path_buf()),
+            StripMode::Debug => "--strip-debug",
+            StripMode::All => "--strip-unneeded",
+        };
+        std::fs::create_dir_all(&dir).map_err(|err| strip_err(&dir, err))?;
+        let mut debuglink = std::ffi::OsString::from("--add-gnu-debuglink=");
+        debuglink.push(&debug);
+
+        self.run(&self.objcopy, &["--only-keep-debug".as_ref(), src.as_os_str(), debug.as_os_str()], src)?;
+        self.run(&self.strip, &[flag.as_ref(), "-o".as_ref(), dst.as_os_str(), src.as_os_str()], src)?;
+        self.run(&self.objcopy, &[debuglink.as_os_str(), dst.as_os_str()], src)?;
+        Ok(dst)
+    }
+
+    /// Replaces ELF executables and libraries of `src` with stripped copies
+    pub fn strip(&self, src: &mut DeployPaths, mode: StripMode) -> DeployResult<()> {
+        if mode == StripMode::None {
+            return Ok(());
+        }
+        for path in src.execs.iter_mut().chain(src.libs.iter_mut()) {
+            if ElfArch::of_file(path).map_err(|err| strip_err(path, err))?.is_none() {
+                continue;
+            }
+            let stripped = self.strip_file(path, mode)?;
+            let size = |p: &Path| p.metadata().map(|m| m.len()).unwrap_or_default();
+            print::info(print::STRIPPED, format!("{} ({} -> {} bytes)", path.display(), size(path), size(&stripped)));
+            *path = stripped;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_runs_without_a_mode_or_for_non_elf_files() {
+        let dir = std::env::temp_dir().join(format!("condep-strip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("tool.sh");
+        std::fs::write(&script, "#!/bin/sh\n").unwrap();
+        let exe = std::env::current_exe().unwrap();
+        let missing = StripTools { strip: dir.join("no-strip"), objcopy: dir.join("no-objcopy") };
+
+        let mut src = DeployPaths { execs: vec![exe.clone()], ..Default::default() };
+        missing.strip(&mut src, StripMode::None).unwrap();
+        assert_eq!(src.execs, [exe]);
+
+        let mut src = DeployPaths { execs: vec![script.clone()], ..Default::default() };
+        missing.strip(&mut src, StripMode::All).unwrap();
+        assert_eq!(src.execs, [script]);
+        assert!(!dir.join(STRIP_DIR_NAME).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}